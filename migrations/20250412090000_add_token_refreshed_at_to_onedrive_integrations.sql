@@ -0,0 +1,3 @@
+-- When the refresh token was last issued or exchanged for an access token, used to keep
+-- idle refresh tokens alive before Microsoft's inactivity expiry
+ALTER TABLE onedrive_integrations ADD COLUMN token_refreshed_at TIMESTAMPTZ;
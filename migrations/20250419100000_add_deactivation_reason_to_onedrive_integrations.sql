@@ -0,0 +1,3 @@
+-- Why and when an integration was deactivated, e.g. a refresh token rejected with invalid_grant
+ALTER TABLE onedrive_integrations ADD COLUMN deactivated_reason TEXT;
+ALTER TABLE onedrive_integrations ADD COLUMN deactivated_at TIMESTAMPTZ;
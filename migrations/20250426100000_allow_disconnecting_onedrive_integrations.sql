@@ -0,0 +1,6 @@
+-- Disconnecting wipes the stored tokens, so the refresh token is no longer always present
+ALTER TABLE onedrive_integrations ALTER COLUMN encrypted_refresh_token DROP NOT NULL;
+
+-- When the owner last disconnected OneDrive. File syncs requested before this are cancelled,
+-- even if the owner has connected again since.
+ALTER TABLE onedrive_integrations ADD COLUMN disconnected_at TIMESTAMPTZ;
@@ -0,0 +1,13 @@
+-- How the integration obtains access tokens: 'delegated' uses a user's refresh token,
+-- 'app_only' uses the client credentials grant against the customer's tenant
+ALTER TABLE onedrive_integrations
+    ADD COLUMN auth_mode TEXT NOT NULL DEFAULT 'delegated'
+    CHECK (auth_mode IN ('delegated', 'app_only'));
+
+-- Tenant that granted admin consent, required for app-only integrations
+ALTER TABLE onedrive_integrations ADD COLUMN tenant_id TEXT;
+
+-- Drive app-only integrations write into: a user's OneDrive (ID or UPN) or a SharePoint site's
+-- default document library
+ALTER TABLE onedrive_integrations ADD COLUMN drive_user TEXT;
+ALTER TABLE onedrive_integrations ADD COLUMN drive_site_id TEXT;
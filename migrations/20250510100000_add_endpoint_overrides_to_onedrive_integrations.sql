@@ -0,0 +1,4 @@
+-- Per-integration Microsoft identity platform and Graph endpoints, for customers in national
+-- clouds. NULL uses the deployment's defaults.
+ALTER TABLE onedrive_integrations ADD COLUMN authority_url TEXT;
+ALTER TABLE onedrive_integrations ADD COLUMN graph_url TEXT;
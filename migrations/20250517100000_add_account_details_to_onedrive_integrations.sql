@@ -0,0 +1,6 @@
+-- Identity of the Microsoft account and drive behind a delegated integration, recorded when it
+-- is authorized, and the scopes Microsoft actually granted
+ALTER TABLE onedrive_integrations ADD COLUMN account_upn TEXT;
+ALTER TABLE onedrive_integrations ADD COLUMN drive_id TEXT;
+ALTER TABLE onedrive_integrations ADD COLUMN drive_type TEXT;
+ALTER TABLE onedrive_integrations ADD COLUMN granted_scopes TEXT;
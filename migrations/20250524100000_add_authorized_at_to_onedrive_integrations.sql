@@ -0,0 +1,3 @@
+-- Timestamp of the authorization message an integration was last saved from, so that delayed
+-- older messages can't overwrite newer credentials
+ALTER TABLE onedrive_integrations ADD COLUMN authorized_at TIMESTAMPTZ;
@@ -0,0 +1,11 @@
+-- Owners are organizations where several users each connect their own OneDrive, so
+-- integrations are keyed by owner and user
+DROP INDEX idx_onedrive_integrations_owner;
+CREATE UNIQUE INDEX idx_onedrive_integrations_owner_user ON onedrive_integrations(owner_id, user_id);
+
+-- Each owner has one default integration, used for messages that don't name a user. Until now
+-- every owner had a single integration, which becomes its default.
+ALTER TABLE onedrive_integrations ADD COLUMN is_default BOOLEAN NOT NULL DEFAULT false;
+UPDATE onedrive_integrations SET is_default = true;
+CREATE UNIQUE INDEX idx_onedrive_integrations_default
+    ON onedrive_integrations(owner_id) WHERE is_default;
@@ -0,0 +1,3 @@
+-- Space-separated scopes the user consented to when authorizing, requested again on every
+-- refresh. NULL means the default of full drive access.
+ALTER TABLE onedrive_integrations ADD COLUMN requested_scopes TEXT;
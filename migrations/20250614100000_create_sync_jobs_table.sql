@@ -0,0 +1,23 @@
+-- One row per file sync request, so its outcome can still be looked up once the message is gone
+CREATE TABLE IF NOT EXISTS sync_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,          -- User whose OneDrive connection the file is synced with
+    source TEXT NOT NULL,             -- Where the file is copied from, e.g. s3://bucket/key
+    destination TEXT NOT NULL,        -- Path the file is written to
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'succeeded', 'failed', 'skipped')),
+    drive_item_id TEXT,               -- ID of the uploaded item once the sync succeeded
+    error TEXT,                       -- Why the sync failed or was skipped
+    requested_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_sync_jobs_owner_user ON sync_jobs(owner_id, user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON sync_jobs
+FOR EACH ROW
+EXECUTE FUNCTION trigger_set_timestamp();
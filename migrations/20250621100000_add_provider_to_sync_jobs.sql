@@ -0,0 +1,8 @@
+-- Files can be synced to destinations other than OneDrive, which aren't reached through a
+-- user's connection
+ALTER TABLE sync_jobs
+ADD COLUMN provider TEXT NOT NULL DEFAULT 'onedrive'
+    CHECK (provider IN ('onedrive', 'local'));
+
+ALTER TABLE sync_jobs
+ALTER COLUMN user_id DROP NOT NULL;
@@ -0,0 +1,29 @@
+-- Dropbox connections, one per owner and user, with tokens encrypted like OneDrive's
+CREATE TABLE IF NOT EXISTS dropbox_integrations (
+    id SERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,                -- ID of the user who authorized this integration
+    account_id TEXT,                        -- Dropbox account the refresh token belongs to
+    encrypted_refresh_token TEXT NOT NULL,  -- Encrypted refresh token (long-lived)
+    encrypted_access_token TEXT,            -- Encrypted access token (short-lived)
+    access_token_expires_at TIMESTAMPTZ,    -- When the access token expires
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    deactivated_reason TEXT,                -- Why Dropbox stopped accepting the refresh token
+    deactivated_at TIMESTAMPTZ,
+    authorized_at TIMESTAMPTZ NOT NULL,     -- When the saved refresh token was issued
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_dropbox_integrations_owner_user
+    ON dropbox_integrations(owner_id, user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON dropbox_integrations
+FOR EACH ROW
+EXECUTE FUNCTION trigger_set_timestamp();
+
+ALTER TABLE sync_jobs DROP CONSTRAINT sync_jobs_provider_check;
+ALTER TABLE sync_jobs
+ADD CONSTRAINT sync_jobs_provider_check
+    CHECK (provider IN ('onedrive', 'local', 'google_drive', 'dropbox'));
@@ -0,0 +1,26 @@
+-- WebDAV servers, such as Nextcloud or ownCloud, one per owner and user, with the password
+-- encrypted like OAuth tokens
+CREATE TABLE IF NOT EXISTS webdav_connections (
+    id SERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,                -- ID of the user who connected the server
+    url TEXT NOT NULL,                      -- Collection synced files are written under
+    username TEXT NOT NULL,
+    encrypted_password TEXT NOT NULL,       -- Encrypted password, usually an app password
+    connected_at TIMESTAMPTZ NOT NULL,      -- When the saved credentials were given
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_webdav_connections_owner_user
+    ON webdav_connections(owner_id, user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON webdav_connections
+FOR EACH ROW
+EXECUTE FUNCTION trigger_set_timestamp();
+
+ALTER TABLE sync_jobs DROP CONSTRAINT sync_jobs_provider_check;
+ALTER TABLE sync_jobs
+ADD CONSTRAINT sync_jobs_provider_check
+    CHECK (provider IN ('onedrive', 'local', 'google_drive', 'dropbox', 'webdav'));
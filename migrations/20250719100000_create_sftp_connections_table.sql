@@ -0,0 +1,29 @@
+-- SFTP servers, one per owner and user, authenticated with a private key encrypted like OAuth
+-- tokens and pinned to the server's host key
+CREATE TABLE IF NOT EXISTS sftp_connections (
+    id SERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,                -- ID of the user who connected the server
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL DEFAULT 22,
+    username TEXT NOT NULL,
+    encrypted_private_key TEXT NOT NULL,    -- Encrypted OpenSSH private key
+    host_key TEXT NOT NULL,                 -- Public host key the server must present
+    root TEXT NOT NULL DEFAULT '',          -- Directory synced files are written under
+    connected_at TIMESTAMPTZ NOT NULL,      -- When the saved credentials were given
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_sftp_connections_owner_user
+    ON sftp_connections(owner_id, user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON sftp_connections
+FOR EACH ROW
+EXECUTE FUNCTION trigger_set_timestamp();
+
+ALTER TABLE sync_jobs DROP CONSTRAINT sync_jobs_provider_check;
+ALTER TABLE sync_jobs
+ADD CONSTRAINT sync_jobs_provider_check
+    CHECK (provider IN ('onedrive', 'local', 'google_drive', 'dropbox', 'webdav', 'sftp'));
@@ -0,0 +1,24 @@
+-- OneDrive folders whose new and modified files are exported to S3, one per owner and user.
+-- Changes are found with Graph's delta queries, resuming from the stored delta link.
+CREATE TABLE IF NOT EXISTS onedrive_watches (
+    id SERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,                -- ID of the user whose integration is watched
+    folder_path TEXT NOT NULL,              -- Watched folder, relative to the drive's root
+    drive_id TEXT,                          -- Drive watched instead of the integration's own
+    bucket TEXT NOT NULL,
+    prefix TEXT NOT NULL DEFAULT '',        -- Key prefix exported files are written under
+    delta_link TEXT,                        -- Where the next delta query resumes, NULL until the first poll
+    registered_at TIMESTAMPTZ NOT NULL,     -- When the watch was requested
+    polled_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_onedrive_watches_owner_user
+    ON onedrive_watches(owner_id, user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON onedrive_watches
+FOR EACH ROW
+EXECUTE FUNCTION trigger_set_timestamp();
@@ -0,0 +1,16 @@
+-- Integrations pick a Microsoft national cloud from a fixed set instead of storing endpoint
+-- URLs taken from queue messages. NULL uses the deployment's configured endpoints.
+ALTER TABLE onedrive_integrations
+    ADD COLUMN cloud TEXT
+    CHECK (cloud IN ('global', 'us_gov', 'china'));
+
+UPDATE onedrive_integrations
+SET cloud = CASE
+    WHEN authority_url LIKE 'https://login.microsoftonline.us%' THEN 'us_gov'
+    WHEN authority_url LIKE 'https://login.chinacloudapi.cn%' THEN 'china'
+    WHEN authority_url LIKE 'https://login.microsoftonline.com%' THEN 'global'
+END
+WHERE authority_url IS NOT NULL;
+
+ALTER TABLE onedrive_integrations DROP COLUMN authority_url;
+ALTER TABLE onedrive_integrations DROP COLUMN graph_url;
@@ -0,0 +1,48 @@
+-- Google Drive and Dropbox connections share one table, one row per provider, owner and user,
+-- with tokens encrypted like OneDrive's
+CREATE TABLE IF NOT EXISTS oauth_integrations (
+    id SERIAL PRIMARY KEY,
+    provider TEXT NOT NULL CHECK (provider IN ('google_drive', 'dropbox')),
+    owner_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,                -- ID of the user who authorized this integration
+    account_id TEXT,                        -- Provider account the refresh token belongs to
+    encrypted_refresh_token TEXT NOT NULL,  -- Encrypted refresh token (long-lived)
+    encrypted_access_token TEXT,            -- Encrypted access token (short-lived)
+    access_token_expires_at TIMESTAMPTZ,    -- When the access token expires
+    granted_scopes TEXT,                    -- Space-separated scopes the user consented to
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    deactivated_reason TEXT,                -- Why the provider stopped accepting the refresh token
+    deactivated_at TIMESTAMPTZ,
+    authorized_at TIMESTAMPTZ NOT NULL,     -- When the saved refresh token was issued
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_oauth_integrations_provider_owner_user
+    ON oauth_integrations(provider, owner_id, user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON oauth_integrations
+FOR EACH ROW
+EXECUTE FUNCTION trigger_set_timestamp();
+
+INSERT INTO oauth_integrations
+    (provider, owner_id, user_id, encrypted_refresh_token, encrypted_access_token,
+     access_token_expires_at, granted_scopes, is_active, deactivated_reason, deactivated_at,
+     authorized_at, created_at, updated_at)
+SELECT 'google_drive', owner_id, user_id, encrypted_refresh_token, encrypted_access_token,
+       access_token_expires_at, granted_scopes, is_active, deactivated_reason, deactivated_at,
+       authorized_at, created_at, updated_at
+FROM google_drive_integrations;
+
+INSERT INTO oauth_integrations
+    (provider, owner_id, user_id, account_id, encrypted_refresh_token, encrypted_access_token,
+     access_token_expires_at, is_active, deactivated_reason, deactivated_at, authorized_at,
+     created_at, updated_at)
+SELECT 'dropbox', owner_id, user_id, account_id, encrypted_refresh_token, encrypted_access_token,
+       access_token_expires_at, is_active, deactivated_reason, deactivated_at, authorized_at,
+       created_at, updated_at
+FROM dropbox_integrations;
+
+DROP TABLE google_drive_integrations;
+DROP TABLE dropbox_integrations;
@@ -1,37 +1,131 @@
 pub struct Config {
     pub database_url: String,
     pub queue_url: String,
+    pub events_queue_url: Option<String>,
     pub aws_region: String,
     pub s3_bucket: String,
     pub s3_endpoint: Option<String>,
+    pub local_source_root: Option<String>,
+    pub local_destination_root: Option<String>,
+    pub azure_storage_account: Option<String>,
+    pub azure_storage_key: Option<String>,
+    pub azure_storage_sas_token: Option<String>,
+    pub azure_blob_endpoint: Option<String>,
     pub encryption_key: String,
     pub onedrive_client_id: String,
     pub onedrive_client_secret: String,
+    pub onedrive_certificate_key_path: Option<String>,
+    pub onedrive_certificate_thumbprint: Option<String>,
+    pub microsoft_authority_url: String,
+    pub microsoft_tenant: String,
+    pub microsoft_graph_url: String,
+    pub google_client_id: Option<String>,
+    pub google_client_secret: Option<String>,
+    pub google_token_url: String,
+    pub google_api_url: String,
+    pub dropbox_app_key: Option<String>,
+    pub dropbox_app_secret: Option<String>,
+    pub dropbox_api_url: String,
+    pub dropbox_content_url: String,
+    pub sftp_program: String,
+    pub allow_insecure_urls: bool,
+    pub token_refresh_interval_secs: u64,
+    pub watch_poll_interval_secs: u64,
+    pub token_refresh_lead_secs: i64,
+    pub refresh_token_keepalive_days: i64,
+    pub authorization_max_age_secs: i64,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
         let database_url = std::env::var("DATABASE_URL")?;
         let queue_url = std::env::var("QUEUE_URL")?;
+        let events_queue_url = std::env::var("EVENTS_QUEUE_URL").ok();
         let aws_region = std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
         let s3_bucket = std::env::var("S3_BUCKET")?;
         let s3_endpoint = std::env::var("S3_ENDPOINT").ok();
+        let local_source_root = std::env::var("LOCAL_SOURCE_ROOT").ok();
+        let local_destination_root = std::env::var("LOCAL_DESTINATION_ROOT").ok();
+        let azure_storage_account = std::env::var("AZURE_STORAGE_ACCOUNT").ok();
+        let azure_storage_key = std::env::var("AZURE_STORAGE_KEY").ok();
+        let azure_storage_sas_token = std::env::var("AZURE_STORAGE_SAS_TOKEN").ok();
+        let azure_blob_endpoint = std::env::var("AZURE_BLOB_ENDPOINT").ok();
         let encryption_key = std::env::var("ENCRYPTION_KEY")
             .unwrap_or_else(|_| "default-dev-key-please-change-in-production".to_string());
         let onedrive_client_id =
             std::env::var("ONEDRIVE_CLIENT_ID").unwrap_or_else(|_| "your-client-id".to_string());
         let onedrive_client_secret = std::env::var("ONEDRIVE_CLIENT_SECRET")
             .unwrap_or_else(|_| "your-client-secret".to_string());
+        let onedrive_certificate_key_path = std::env::var("ONEDRIVE_CERTIFICATE_KEY_PATH").ok();
+        let onedrive_certificate_thumbprint = std::env::var("ONEDRIVE_CERTIFICATE_THUMBPRINT").ok();
+        let microsoft_authority_url = std::env::var("MICROSOFT_AUTHORITY_URL")
+            .unwrap_or_else(|_| "https://login.microsoftonline.com".to_string());
+        let microsoft_tenant =
+            std::env::var("MICROSOFT_TENANT").unwrap_or_else(|_| "common".to_string());
+        let microsoft_graph_url = std::env::var("MICROSOFT_GRAPH_URL")
+            .unwrap_or_else(|_| "https://graph.microsoft.com/v1.0".to_string());
+        let google_client_id = std::env::var("GOOGLE_CLIENT_ID").ok();
+        let google_client_secret = std::env::var("GOOGLE_CLIENT_SECRET").ok();
+        let google_token_url = std::env::var("GOOGLE_TOKEN_URL")
+            .unwrap_or_else(|_| "https://oauth2.googleapis.com/token".to_string());
+        let google_api_url = std::env::var("GOOGLE_API_URL")
+            .unwrap_or_else(|_| "https://www.googleapis.com".to_string());
+        let dropbox_app_key = std::env::var("DROPBOX_APP_KEY").ok();
+        let dropbox_app_secret = std::env::var("DROPBOX_APP_SECRET").ok();
+        let dropbox_api_url = std::env::var("DROPBOX_API_URL")
+            .unwrap_or_else(|_| "https://api.dropboxapi.com".to_string());
+        let dropbox_content_url = std::env::var("DROPBOX_CONTENT_URL")
+            .unwrap_or_else(|_| "https://content.dropboxapi.com".to_string());
+        let sftp_program = std::env::var("SFTP_PROGRAM").unwrap_or_else(|_| "sftp".to_string());
+        let allow_insecure_urls =
+            std::env::var("ALLOW_INSECURE_URLS").ok().map(|v| v.parse()).transpose()?;
+        let token_refresh_interval_secs =
+            std::env::var("TOKEN_REFRESH_INTERVAL_SECS").ok().map(|v| v.parse()).transpose()?;
+        let watch_poll_interval_secs =
+            std::env::var("WATCH_POLL_INTERVAL_SECS").ok().map(|v| v.parse()).transpose()?;
+        let token_refresh_lead_secs =
+            std::env::var("TOKEN_REFRESH_LEAD_SECS").ok().map(|v| v.parse()).transpose()?;
+        let refresh_token_keepalive_days =
+            std::env::var("REFRESH_TOKEN_KEEPALIVE_DAYS").ok().map(|v| v.parse()).transpose()?;
+        let authorization_max_age_secs =
+            std::env::var("AUTHORIZATION_MAX_AGE_SECS").ok().map(|v| v.parse()).transpose()?;
 
         Ok(Config {
             database_url,
             queue_url,
+            events_queue_url,
             aws_region,
             s3_bucket,
             s3_endpoint,
+            local_source_root,
+            local_destination_root,
+            azure_storage_account,
+            azure_storage_key,
+            azure_storage_sas_token,
+            azure_blob_endpoint,
             encryption_key,
             onedrive_client_id,
             onedrive_client_secret,
+            onedrive_certificate_key_path,
+            onedrive_certificate_thumbprint,
+            microsoft_authority_url,
+            microsoft_tenant,
+            microsoft_graph_url,
+            google_client_id,
+            google_client_secret,
+            google_token_url,
+            google_api_url,
+            dropbox_app_key,
+            dropbox_app_secret,
+            dropbox_api_url,
+            dropbox_content_url,
+            sftp_program,
+            allow_insecure_urls: allow_insecure_urls.unwrap_or(false),
+            token_refresh_interval_secs: token_refresh_interval_secs.unwrap_or(60),
+            watch_poll_interval_secs: watch_poll_interval_secs.unwrap_or(300),
+            token_refresh_lead_secs: token_refresh_lead_secs.unwrap_or(600),
+            refresh_token_keepalive_days: refresh_token_keepalive_days.unwrap_or(30),
+            authorization_max_age_secs: authorization_max_age_secs.unwrap_or(3600),
         })
     }
 }
@@ -84,4 +84,3 @@ mod tests {
         Ok(())
     }
 }
-
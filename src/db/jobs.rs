@@ -0,0 +1,62 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query_as, PgPool};
+
+use crate::db::models::{Provider, SyncJob, SyncJobStatus};
+
+/// Record a file sync that is about to be attempted
+pub async fn create_sync_job(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: Option<i64>,
+    provider: Provider,
+    source: &str,
+    destination: &str,
+    requested_at: DateTime<Utc>,
+) -> Result<SyncJob> {
+    let job = query_as!(
+        SyncJob,
+        r#"
+        INSERT INTO sync_jobs (owner_id, user_id, provider, source, destination, requested_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, owner_id, user_id, provider AS "provider: Provider", source, destination,
+                  status AS "status: SyncJobStatus", drive_item_id, error, requested_at,
+                  finished_at, created_at, updated_at
+        "#,
+        owner_id,
+        user_id,
+        provider as Provider,
+        source,
+        destination,
+        requested_at,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(job)
+}
+
+/// Record the outcome of a file sync
+pub async fn finish_sync_job(
+    pool: &PgPool,
+    id: i64,
+    status: SyncJobStatus,
+    drive_item_id: Option<&str>,
+    error: Option<&str>,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE sync_jobs
+        SET status = $2, drive_item_id = $3, error = $4, finished_at = NOW(), updated_at = NOW()
+        WHERE id = $1
+        "#,
+        id,
+        status as SyncJobStatus,
+        drive_item_id,
+        error,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,890 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use super::models::{
+    MicrosoftCloud, OAuthAccessToken, OAuthGrant, OAuthIntegration, OneDriveAccessToken,
+    OneDriveAuthMode, OneDriveIntegration, OneDriveRefreshToken, OneDriveWatch, Provider,
+    SftpConnection, SftpCredentials, SyncJob, SyncJobStatus, TokenRefreshCandidate, WatchedFolder,
+    WebDavConnection, WebDavCredentials,
+};
+use super::repository::{
+    IntegrationRepository, JobRepository, OAuthRepository, SftpRepository, TokenRefreshLock,
+    WatchRepository, WebDavRepository,
+};
+
+/// An integration with the columns `OneDriveIntegration` doesn't expose
+struct StoredIntegration {
+    integration: OneDriveIntegration,
+    refresh_token: Option<String>,
+    access_token: Option<String>,
+    token_refreshed_at: DateTime<Utc>,
+    disconnected_at: Option<DateTime<Utc>>,
+}
+
+/// A Google Drive or Dropbox integration with its tokens
+struct StoredOAuth {
+    integration: OAuthIntegration,
+    refresh_token: String,
+    access_token: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    integrations: BTreeMap<(i64, i64), StoredIntegration>,
+    oauth: HashMap<(Provider, i64, i64), StoredOAuth>,
+    webdav: BTreeMap<(i64, i64), (WebDavConnection, WebDavCredentials)>,
+    sftp: BTreeMap<(i64, i64), (SftpConnection, SftpCredentials)>,
+    watches: BTreeMap<(i64, i64), OneDriveWatch>,
+    jobs: Vec<SyncJob>,
+}
+
+impl State {
+    fn has_default(&self, owner_id: i64) -> bool {
+        self.integrations
+            .values()
+            .any(|s| s.integration.owner_id == owner_id && s.integration.is_default)
+    }
+
+    fn active(&mut self, owner_id: i64, user_id: i64) -> Option<&mut StoredIntegration> {
+        self.integrations.get_mut(&(owner_id, user_id)).filter(|s| s.integration.is_active)
+    }
+
+    /// Insert or reactivate an integration for an authorization made at `authorized_at`, or
+    /// return `None` if it was already authorized more recently
+    fn authorize(
+        &mut self,
+        owner_id: i64,
+        user_id: i64,
+        authorized_at: DateTime<Utc>,
+    ) -> Option<&mut StoredIntegration> {
+        let now = Utc::now();
+        let has_default = self.has_default(owner_id);
+        let next_id = self.integrations.len() as i32 + 1;
+
+        let stored =
+            self.integrations.entry((owner_id, user_id)).or_insert_with(|| StoredIntegration {
+                integration: OneDriveIntegration {
+                    id: next_id,
+                    owner_id,
+                    user_id,
+                    auth_mode: OneDriveAuthMode::Delegated,
+                    tenant_id: None,
+                    cloud: None,
+                    drive_user: None,
+                    drive_site_id: None,
+                    account_upn: None,
+                    drive_id: None,
+                    drive_type: None,
+                    requested_scopes: None,
+                    granted_scopes: None,
+                    access_token_expires_at: None,
+                    is_active: false,
+                    is_default: false,
+                    deactivated_reason: None,
+                    deactivated_at: None,
+                    authorized_at: None,
+                    created_at: now,
+                    updated_at: now,
+                },
+                refresh_token: None,
+                access_token: None,
+                token_refreshed_at: now,
+                disconnected_at: None,
+            });
+
+        if stored.integration.authorized_at.is_some_and(|previous| previous >= authorized_at) {
+            return None;
+        }
+
+        let integration = &mut stored.integration;
+        integration.is_active = true;
+        integration.is_default = integration.is_default || !has_default;
+        integration.deactivated_reason = None;
+        integration.deactivated_at = None;
+        integration.authorized_at = Some(authorized_at);
+        integration.updated_at = now;
+        stored.token_refreshed_at = now;
+
+        Some(stored)
+    }
+}
+
+/// Repository that keeps everything in memory, for tests of code built on the repositories
+#[derive(Default)]
+pub struct InMemoryRepository {
+    state: Mutex<State>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every sync job recorded so far, oldest first
+    pub fn sync_jobs(&self) -> Vec<SyncJob> {
+        self.state.lock().unwrap().jobs.clone()
+    }
+}
+
+#[async_trait]
+impl IntegrationRepository for InMemoryRepository {
+    async fn get_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveIntegration>> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.active(owner_id, user_id).map(|s| s.integration.clone()))
+    }
+
+    async fn get_deactivated_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveIntegration>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .integrations
+            .get(&(owner_id, user_id))
+            .filter(|s| !s.integration.is_active)
+            .map(|s| s.integration.clone()))
+    }
+
+    async fn get_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveRefreshToken>> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state
+            .active(owner_id, user_id)
+            .and_then(|s| s.refresh_token.clone())
+            .map(|refresh_token| OneDriveRefreshToken { refresh_token }))
+    }
+
+    async fn get_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveAccessToken>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.active(owner_id, user_id) else {
+            return Ok(None);
+        };
+
+        match (&stored.access_token, stored.integration.access_token_expires_at) {
+            (Some(access_token), Some(expires_at)) if expires_at > Utc::now() => {
+                Ok(Some(OneDriveAccessToken { access_token: access_token.clone(), expires_at }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn save_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refresh_token: &str,
+        requested_scopes: Option<&str>,
+        authorized_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveIntegration>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.authorize(owner_id, user_id, authorized_at) else {
+            return Ok(None);
+        };
+
+        // An app-only access token must not be used on behalf of the user
+        if stored.integration.auth_mode != OneDriveAuthMode::Delegated {
+            stored.access_token = None;
+            stored.integration.access_token_expires_at = None;
+        }
+
+        stored.integration.auth_mode = OneDriveAuthMode::Delegated;
+        stored.integration.drive_user = None;
+        stored.integration.drive_site_id = None;
+        stored.integration.requested_scopes = requested_scopes.map(str::to_string);
+        stored.refresh_token = Some(refresh_token.to_string());
+
+        Ok(Some(stored.integration.clone()))
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refresh_token: &str,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.active(owner_id, user_id) else {
+            return Ok(false);
+        };
+
+        stored.refresh_token = Some(refresh_token.to_string());
+        stored.token_refreshed_at = Utc::now();
+
+        Ok(true)
+    }
+
+    async fn save_tenant_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        tenant_id: &str,
+        drive_user: Option<&str>,
+        drive_site_id: Option<&str>,
+        authorized_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveIntegration>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.authorize(owner_id, user_id, authorized_at) else {
+            return Ok(None);
+        };
+
+        stored.integration.auth_mode = OneDriveAuthMode::AppOnly;
+        stored.integration.tenant_id = Some(tenant_id.to_string());
+        stored.integration.drive_user = drive_user.map(str::to_string);
+        stored.integration.drive_site_id = drive_site_id.map(str::to_string);
+        stored.integration.requested_scopes = None;
+        stored.integration.access_token_expires_at = None;
+        stored.refresh_token = None;
+        stored.access_token = None;
+
+        Ok(Some(stored.integration.clone()))
+    }
+
+    async fn save_endpoint_overrides(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        tenant_id: Option<&str>,
+        cloud: Option<MicrosoftCloud>,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.integrations.get_mut(&(owner_id, user_id)) else {
+            return Ok(false);
+        };
+
+        stored.integration.tenant_id = tenant_id.map(str::to_string);
+        stored.integration.cloud = cloud;
+        stored.integration.updated_at = Utc::now();
+
+        Ok(true)
+    }
+
+    async fn save_account_details(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        account_upn: Option<&str>,
+        drive_id: &str,
+        drive_type: &str,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.active(owner_id, user_id) else {
+            return Ok(false);
+        };
+
+        stored.integration.account_upn = account_upn.map(str::to_string);
+        stored.integration.drive_id = Some(drive_id.to_string());
+        stored.integration.drive_type = Some(drive_type.to_string());
+        stored.integration.updated_at = Utc::now();
+
+        Ok(true)
+    }
+
+    async fn save_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        access_token: &str,
+        expires_at: DateTime<Utc>,
+        granted_scopes: Option<&str>,
+    ) -> Result<OneDriveIntegration> {
+        let mut state = self.state.lock().unwrap();
+        let stored = state.active(owner_id, user_id).context("No active OneDrive integration")?;
+
+        stored.access_token = Some(access_token.to_string());
+        stored.integration.access_token_expires_at = Some(expires_at);
+        if let Some(granted_scopes) = granted_scopes {
+            stored.integration.granted_scopes = Some(granted_scopes.to_string());
+        }
+        stored.token_refreshed_at = Utc::now();
+        stored.integration.updated_at = Utc::now();
+
+        Ok(stored.integration.clone())
+    }
+
+    async fn get_token_refresh_candidates(
+        &self,
+        expiring_before: DateTime<Utc>,
+        idle_since: DateTime<Utc>,
+    ) -> Result<Vec<TokenRefreshCandidate>> {
+        let now = Utc::now();
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .integrations
+            .values()
+            .filter(|s| s.integration.is_active)
+            .filter_map(|s| {
+                let expiring = s
+                    .integration
+                    .access_token_expires_at
+                    .is_some_and(|expires_at| expires_at > now && expires_at < expiring_before);
+                let refresh_token_idle = s.token_refreshed_at < idle_since;
+
+                (expiring || refresh_token_idle).then_some(TokenRefreshCandidate {
+                    owner_id: s.integration.owner_id,
+                    user_id: s.integration.user_id,
+                    refresh_token_idle,
+                    token_refreshed_at: s.token_refreshed_at,
+                })
+            })
+            .collect())
+    }
+
+    async fn get_token_refreshed_at(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.integrations.get(&(owner_id, user_id)).map(|s| s.token_refreshed_at))
+    }
+
+    async fn deactivate_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.integrations.get_mut(&(owner_id, user_id)) else {
+            return Ok(false);
+        };
+
+        stored.integration.is_active = false;
+        stored.integration.deactivated_reason = Some(reason.to_string());
+        stored.integration.deactivated_at = Some(Utc::now());
+
+        Ok(true)
+    }
+
+    async fn disconnect_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        requested_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.integrations.get_mut(&(owner_id, user_id)) else {
+            return Ok(false);
+        };
+        if stored
+            .integration
+            .authorized_at
+            .is_some_and(|authorized_at| authorized_at >= requested_at)
+        {
+            return Ok(false);
+        }
+
+        let was_default = stored.integration.is_default;
+        stored.integration.is_active = false;
+        stored.integration.is_default = false;
+        stored.integration.access_token_expires_at = None;
+        stored.integration.deactivated_reason = Some("Disconnected by user".to_string());
+        stored.integration.deactivated_at = Some(now);
+        stored.integration.updated_at = now;
+        stored.refresh_token = None;
+        stored.access_token = None;
+        stored.disconnected_at = Some(now);
+
+        if was_default {
+            if let Some(oldest) = state
+                .integrations
+                .values_mut()
+                .filter(|s| s.integration.owner_id == owner_id && s.integration.is_active)
+                .min_by_key(|s| s.integration.created_at)
+            {
+                oldest.integration.is_default = true;
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn get_disconnected_at(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.integrations.get(&(owner_id, user_id)).and_then(|s| s.disconnected_at))
+    }
+
+    async fn get_default_user_id(&self, owner_id: i64) -> Result<Option<i64>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .integrations
+            .values()
+            .find(|s| s.integration.owner_id == owner_id && s.integration.is_default)
+            .map(|s| s.integration.user_id))
+    }
+
+    async fn lock_token_refresh(
+        &self,
+        _owner_id: i64,
+        _user_id: i64,
+    ) -> Result<Box<dyn TokenRefreshLock>> {
+        // A single process shares this repository, and `RefreshLocks` already serializes it
+        Ok(Box::new(()))
+    }
+}
+
+#[async_trait]
+impl TokenRefreshLock for () {
+    async fn release(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobRepository for InMemoryRepository {
+    async fn create_sync_job(
+        &self,
+        owner_id: i64,
+        user_id: Option<i64>,
+        provider: Provider,
+        source: &str,
+        destination: &str,
+        requested_at: DateTime<Utc>,
+    ) -> Result<SyncJob> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+
+        let job = SyncJob {
+            id: state.jobs.len() as i64 + 1,
+            owner_id,
+            user_id,
+            provider,
+            source: source.to_string(),
+            destination: destination.to_string(),
+            status: SyncJobStatus::Pending,
+            drive_item_id: None,
+            error: None,
+            requested_at,
+            finished_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+        state.jobs.push(job.clone());
+
+        Ok(job)
+    }
+
+    async fn finish_sync_job(
+        &self,
+        id: i64,
+        status: SyncJobStatus,
+        drive_item_id: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let Some(job) = state.jobs.iter_mut().find(|job| job.id == id) else {
+            return Ok(false);
+        };
+
+        job.status = status;
+        job.drive_item_id = drive_item_id.map(str::to_string);
+        job.error = error.map(str::to_string);
+        job.finished_at = Some(now);
+        job.updated_at = now;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl OAuthRepository for InMemoryRepository {
+    async fn get_oauth_integration(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OAuthIntegration>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.oauth.get(&(provider, owner_id, user_id)).map(|s| s.integration.clone()))
+    }
+
+    async fn get_oauth_refresh_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<String>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .oauth
+            .get(&(provider, owner_id, user_id))
+            .filter(|s| s.integration.is_active)
+            .map(|s| s.refresh_token.clone()))
+    }
+
+    async fn get_oauth_access_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OAuthAccessToken>> {
+        let state = self.state.lock().unwrap();
+        let Some(stored) =
+            state.oauth.get(&(provider, owner_id, user_id)).filter(|s| s.integration.is_active)
+        else {
+            return Ok(None);
+        };
+
+        Ok(match (&stored.access_token, stored.integration.access_token_expires_at) {
+            (Some(access_token), Some(expires_at)) if expires_at > Utc::now() => {
+                Some(OAuthAccessToken { access_token: access_token.clone(), expires_at })
+            }
+            _ => None,
+        })
+    }
+
+    async fn save_oauth_refresh_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        grant: &OAuthGrant,
+    ) -> Result<Option<OAuthIntegration>> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let next_id = state.oauth.len() as i32 + 1;
+        let key = (provider, owner_id, user_id);
+
+        if let Some(stored) = state.oauth.get(&key) {
+            if stored.integration.authorized_at >= grant.authorized_at {
+                return Ok(None);
+            }
+        }
+
+        // Reauthorizing keeps the integration's identity
+        let (id, created_at) = state.oauth.get(&key).map_or((next_id, now), |stored| {
+            (stored.integration.id, stored.integration.created_at)
+        });
+        let integration = OAuthIntegration {
+            id,
+            provider,
+            owner_id,
+            user_id,
+            account_id: grant.account_id.clone(),
+            granted_scopes: grant.granted_scopes.clone(),
+            access_token_expires_at: None,
+            is_active: true,
+            deactivated_reason: None,
+            deactivated_at: None,
+            authorized_at: grant.authorized_at,
+            created_at,
+            updated_at: now,
+        };
+        state.oauth.insert(
+            key,
+            StoredOAuth {
+                integration: integration.clone(),
+                refresh_token: grant.refresh_token.clone(),
+                access_token: None,
+            },
+        );
+
+        Ok(Some(integration))
+    }
+
+    async fn save_oauth_access_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        access_token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) =
+            state.oauth.get_mut(&(provider, owner_id, user_id)).filter(|s| s.integration.is_active)
+        else {
+            return Ok(false);
+        };
+
+        stored.access_token = Some(access_token.to_string());
+        stored.integration.access_token_expires_at = Some(expires_at);
+        stored.integration.updated_at = Utc::now();
+
+        Ok(true)
+    }
+
+    async fn deactivate_oauth_integration(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let Some(stored) = state.oauth.get_mut(&(provider, owner_id, user_id)) else {
+            return Ok(false);
+        };
+
+        stored.access_token = None;
+        stored.integration.access_token_expires_at = None;
+        stored.integration.is_active = false;
+        stored.integration.deactivated_reason = Some(reason.to_string());
+        stored.integration.deactivated_at = Some(now);
+        stored.integration.updated_at = now;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl WebDavRepository for InMemoryRepository {
+    async fn get_webdav_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WebDavConnection>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.webdav.get(&(owner_id, user_id)).map(|(connection, _)| connection.clone()))
+    }
+
+    async fn get_webdav_credentials(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WebDavCredentials>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.webdav.get(&(owner_id, user_id)).map(|(_, credentials)| credentials.clone()))
+    }
+
+    async fn save_webdav_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        credentials: &WebDavCredentials,
+        connected_at: DateTime<Utc>,
+    ) -> Result<Option<WebDavConnection>> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let next_id = state.webdav.len() as i32 + 1;
+
+        let existing = state.webdav.get(&(owner_id, user_id)).map(|(connection, _)| connection);
+        if existing.is_some_and(|connection| connection.connected_at >= connected_at) {
+            return Ok(None);
+        }
+
+        let (id, created_at) = existing.map_or((next_id, now), |c| (c.id, c.created_at));
+        let connection = WebDavConnection {
+            id,
+            owner_id,
+            user_id,
+            url: credentials.url.clone(),
+            username: credentials.username.clone(),
+            connected_at,
+            created_at,
+            updated_at: now,
+        };
+        state.webdav.insert((owner_id, user_id), (connection.clone(), credentials.clone()));
+
+        Ok(Some(connection))
+    }
+}
+
+#[async_trait]
+impl SftpRepository for InMemoryRepository {
+    async fn get_sftp_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<SftpConnection>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.sftp.get(&(owner_id, user_id)).map(|(connection, _)| connection.clone()))
+    }
+
+    async fn get_sftp_credentials(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<SftpCredentials>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.sftp.get(&(owner_id, user_id)).map(|(_, credentials)| credentials.clone()))
+    }
+
+    async fn save_sftp_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        credentials: &SftpCredentials,
+        connected_at: DateTime<Utc>,
+    ) -> Result<Option<SftpConnection>> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let next_id = state.sftp.len() as i32 + 1;
+
+        let existing = state.sftp.get(&(owner_id, user_id)).map(|(connection, _)| connection);
+        if existing.is_some_and(|connection| connection.connected_at >= connected_at) {
+            return Ok(None);
+        }
+
+        let (id, created_at) = existing.map_or((next_id, now), |c| (c.id, c.created_at));
+        let connection = SftpConnection {
+            id,
+            owner_id,
+            user_id,
+            host: credentials.host.clone(),
+            port: credentials.port,
+            username: credentials.username.clone(),
+            host_key: credentials.host_key.clone(),
+            root: credentials.root.clone(),
+            connected_at,
+            created_at,
+            updated_at: now,
+        };
+        state.sftp.insert((owner_id, user_id), (connection.clone(), credentials.clone()));
+
+        Ok(Some(connection))
+    }
+}
+
+#[async_trait]
+impl WatchRepository for InMemoryRepository {
+    async fn claim_due_onedrive_watches(
+        &self,
+        due_before: DateTime<Utc>,
+    ) -> Result<Vec<OneDriveWatch>> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let State { integrations, watches, .. } = &mut *state;
+        let is_due = |watch: &OneDriveWatch| {
+            let stored = integrations.get(&(watch.owner_id, watch.user_id));
+            stored.is_some_and(|stored| stored.integration.is_active)
+                && watch.polled_at.is_none_or(|polled_at| polled_at < due_before)
+        };
+
+        Ok(watches
+            .values_mut()
+            .filter(|watch| is_due(watch))
+            .map(|watch| {
+                watch.polled_at = Some(now);
+                watch.updated_at = now;
+                watch.clone()
+            })
+            .collect())
+    }
+
+    async fn save_onedrive_watch(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        folder: &WatchedFolder,
+        registered_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveWatch>> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let next_id = state.watches.len() as i32 + 1;
+
+        let existing = state.watches.get(&(owner_id, user_id));
+        if existing.is_some_and(|watch| watch.registered_at >= registered_at) {
+            return Ok(None);
+        }
+
+        let (id, created_at) = existing.map_or((next_id, now), |w| (w.id, w.created_at));
+        let watch = OneDriveWatch {
+            id,
+            owner_id,
+            user_id,
+            folder_path: folder.folder_path.clone(),
+            drive_id: folder.drive_id.clone(),
+            bucket: folder.bucket.clone(),
+            prefix: folder.prefix.clone(),
+            delta_link: None,
+            registered_at,
+            polled_at: None,
+            created_at,
+            updated_at: now,
+        };
+        state.watches.insert((owner_id, user_id), watch.clone());
+
+        Ok(Some(watch))
+    }
+
+    async fn save_onedrive_delta_link(
+        &self,
+        watch_id: i32,
+        registered_at: DateTime<Utc>,
+        delta_link: &str,
+    ) -> Result<bool> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let watch = state
+            .watches
+            .values_mut()
+            .find(|watch| watch.id == watch_id && watch.registered_at == registered_at);
+
+        Ok(watch
+            .map(|watch| {
+                watch.delta_link = Some(delta_link.to_string());
+                watch.polled_at = Some(now);
+                watch.updated_at = now;
+            })
+            .is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn test_stale_authorization_is_ignored() -> Result<()> {
+        let repository = InMemoryRepository::new();
+        let now = Utc::now();
+
+        assert!(repository.save_refresh_token(1, 2, "new", None, now).await?.is_some());
+        assert!(repository
+            .save_refresh_token(1, 2, "old", None, now - Duration::minutes(1))
+            .await?
+            .is_none());
+
+        let refresh_token = repository.get_refresh_token(1, 2).await?.unwrap();
+        assert_eq!(refresh_token.refresh_token, "new");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_passes_default_on() -> Result<()> {
+        let repository = InMemoryRepository::new();
+        let now = Utc::now();
+
+        repository.save_refresh_token(1, 2, "first", None, now).await?;
+        repository.save_refresh_token(1, 3, "second", None, now).await?;
+        repository.save_refresh_token(4, 5, "other owner", None, now).await?;
+        assert_eq!(repository.get_default_user_id(1).await?, Some(2));
+
+        assert!(repository.disconnect_integration(1, 2, Utc::now()).await?);
+        assert_eq!(repository.get_default_user_id(1).await?, Some(3));
+        assert!(repository.get_refresh_token(1, 2).await?.is_none());
+        assert!(repository.get_disconnected_at(1, 2).await?.is_some());
+
+        // Reconnecting doesn't take the default back
+        repository.save_refresh_token(1, 2, "again", None, Utc::now()).await?;
+        assert_eq!(repository.get_default_user_id(1).await?, Some(3));
+
+        Ok(())
+    }
+}
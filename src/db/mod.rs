@@ -1,7 +1,15 @@
 pub mod encryption;
+pub mod jobs;
+#[cfg(test)]
+pub mod memory;
 pub mod migrations;
 pub mod models;
+pub mod oauth;
 pub mod onedrive;
+pub mod repository;
+pub mod sftp;
+pub mod watches;
+pub mod webdav;
 
 use sqlx::postgres::PgPool;
 use sqlx::postgres::PgPoolOptions;
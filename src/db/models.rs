@@ -8,14 +8,53 @@ pub struct File {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How an integration obtains access tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OneDriveAuthMode {
+    /// Acts on behalf of the user who authorized it, using their refresh token
+    Delegated,
+    /// Acts as the application itself in a tenant that granted admin consent
+    AppOnly,
+}
+
+/// Microsoft national clouds an integration can be in, each with fixed sign-in and Graph hosts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MicrosoftCloud {
+    Global,
+    UsGov,
+    China,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OneDriveIntegration {
     pub id: i32,
     pub owner_id: i64,
     pub user_id: i64,
+    pub auth_mode: OneDriveAuthMode,
+    pub tenant_id: Option<String>,
+    /// National cloud the integration lives in, `None` for the deployment's endpoints
+    pub cloud: Option<MicrosoftCloud>,
+    pub drive_user: Option<String>,
+    pub drive_site_id: Option<String>,
+    pub account_upn: Option<String>,
+    pub drive_id: Option<String>,
+    pub drive_type: Option<String>,
+    /// Space-separated scopes requested when refreshing, `None` for full drive access
+    pub requested_scopes: Option<String>,
+    /// Space-separated scopes Microsoft granted with the latest access token
+    pub granted_scopes: Option<String>,
     // Note: encrypted tokens are managed internally and not exposed directly
     pub access_token_expires_at: Option<DateTime<Utc>>,
     pub is_active: bool,
+    /// The owner's integration used for messages that don't name a user
+    pub is_default: bool,
+    pub deactivated_reason: Option<String>,
+    pub deactivated_at: Option<DateTime<Utc>>,
+    pub authorized_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -30,3 +69,190 @@ pub struct OneDriveAccessToken {
     pub access_token: String,
     pub expires_at: DateTime<Utc>,
 }
+
+/// A Google Drive or Dropbox integration, authorized with a refresh token the user granted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthIntegration {
+    pub id: i32,
+    pub provider: Provider,
+    pub owner_id: i64,
+    pub user_id: i64,
+    /// Provider account the refresh token belongs to, if the product reported it
+    pub account_id: Option<String>,
+    /// Space-separated scopes the user consented to, if the product reported them
+    pub granted_scopes: Option<String>,
+    pub access_token_expires_at: Option<DateTime<Utc>>,
+    pub is_active: bool,
+    pub deactivated_reason: Option<String>,
+    pub deactivated_at: Option<DateTime<Utc>>,
+    pub authorized_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A refresh token the product obtained for a Google Drive or Dropbox integration
+#[derive(Debug, Clone)]
+pub struct OAuthGrant {
+    pub refresh_token: String,
+    pub account_id: Option<String>,
+    pub granted_scopes: Option<String>,
+    /// When the user authorized, so older grants arriving late don't replace newer ones
+    pub authorized_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthAccessToken {
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A user's WebDAV server. The password is only handed out with [`WebDavCredentials`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebDavConnection {
+    pub id: i32,
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub url: String,
+    pub username: String,
+    pub connected_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebDavCredentials {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// A user's SFTP server. The private key is only handed out with [`SftpCredentials`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpConnection {
+    pub id: i32,
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub host: String,
+    pub port: i32,
+    pub username: String,
+    pub host_key: String,
+    pub root: String,
+    pub connected_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SftpCredentials {
+    pub host: String,
+    pub port: i32,
+    pub username: String,
+    /// OpenSSH private key
+    pub private_key: String,
+    /// Public host key the server must present, e.g. `ssh-ed25519 AAAAC3...`
+    pub host_key: String,
+    /// Directory synced files are written under, the login directory if empty
+    pub root: String,
+}
+
+/// A OneDrive folder whose new and modified files are exported to S3
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OneDriveWatch {
+    pub id: i32,
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub folder_path: String,
+    pub drive_id: Option<String>,
+    pub bucket: String,
+    pub prefix: String,
+    /// Where the next delta query resumes, `None` until the folder was first polled
+    pub delta_link: Option<String>,
+    pub registered_at: DateTime<Utc>,
+    pub polled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What a watch registration asks for
+#[derive(Debug, Clone)]
+pub struct WatchedFolder {
+    /// Path relative to the drive's root
+    pub folder_path: String,
+    /// Drive to watch instead of the integration's own
+    pub drive_id: Option<String>,
+    pub bucket: String,
+    pub prefix: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenRefreshCandidate {
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub refresh_token_idle: bool,
+    /// When the refresh token was last exchanged, as of selecting the candidate
+    pub token_refreshed_at: DateTime<Utc>,
+}
+
+/// Where a file sync stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SyncJobStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    /// Not attempted, e.g. because the integration was disconnected or needs reauthorization
+    Skipped,
+}
+
+/// Where synced files are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    #[default]
+    #[sqlx(rename = "onedrive")]
+    #[serde(rename = "onedrive")]
+    OneDrive,
+    /// A directory on the worker's filesystem
+    Local,
+    GoogleDrive,
+    Dropbox,
+    /// A WebDAV server, such as Nextcloud or ownCloud
+    #[sqlx(rename = "webdav")]
+    #[serde(rename = "webdav")]
+    WebDav,
+    Sftp,
+}
+
+impl Provider {
+    /// The provider's name in log messages and job errors
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::OneDrive => "OneDrive",
+            Self::Local => "Local",
+            Self::GoogleDrive => "Google Drive",
+            Self::Dropbox => "Dropbox",
+            Self::WebDav => "WebDAV",
+            Self::Sftp => "SFTP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncJob {
+    pub id: i64,
+    pub owner_id: i64,
+    /// User whose connection the file is synced with, for providers that have them
+    pub user_id: Option<i64>,
+    pub provider: Provider,
+    pub source: String,
+    pub destination: String,
+    pub status: SyncJobStatus,
+    pub drive_item_id: Option<String>,
+    pub error: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
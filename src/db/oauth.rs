@@ -0,0 +1,200 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query_as, PgPool};
+
+use crate::db::encryption::{decrypt_token, encrypt_token};
+use crate::db::models::{OAuthAccessToken, OAuthGrant, OAuthIntegration, Provider};
+
+/// Get a user's integration with a provider, whether or not it is still active
+pub async fn get_integration(
+    pool: &PgPool,
+    provider: Provider,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<OAuthIntegration>> {
+    let integration = query_as!(
+        OAuthIntegration,
+        r#"
+        SELECT id, provider AS "provider: Provider", owner_id, user_id, account_id, granted_scopes,
+               access_token_expires_at, is_active, deactivated_reason, deactivated_at,
+               authorized_at, created_at, updated_at
+        FROM oauth_integrations
+        WHERE provider = $1 AND owner_id = $2 AND user_id = $3
+        "#,
+        provider as Provider,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(integration)
+}
+
+pub async fn get_refresh_token(
+    pool: &PgPool,
+    provider: Provider,
+    owner_id: i64,
+    user_id: i64,
+    encryption_key: &str,
+) -> Result<Option<String>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT encrypted_refresh_token
+        FROM oauth_integrations
+        WHERE provider = $1 AND owner_id = $2 AND user_id = $3 AND is_active = true
+        "#,
+        provider as Provider,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    record.map(|record| decrypt_token(&record.encrypted_refresh_token, encryption_key)).transpose()
+}
+
+/// Get the cached access token of a user's active integration unless it has expired
+pub async fn get_access_token(
+    pool: &PgPool,
+    provider: Provider,
+    owner_id: i64,
+    user_id: i64,
+    encryption_key: &str,
+) -> Result<Option<OAuthAccessToken>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT encrypted_access_token AS "encrypted_access_token!",
+               access_token_expires_at AS "access_token_expires_at!"
+        FROM oauth_integrations
+        WHERE provider = $1
+          AND owner_id = $2
+          AND user_id = $3
+          AND is_active = true
+          AND encrypted_access_token IS NOT NULL
+          AND access_token_expires_at > NOW()
+        "#,
+        provider as Provider,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match record {
+        Some(record) => Ok(Some(OAuthAccessToken {
+            access_token: decrypt_token(&record.encrypted_access_token, encryption_key)?,
+            expires_at: record.access_token_expires_at,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Save the refresh token of a grant, reactivating the integration. Returns `None` without
+/// changing anything if the integration was already authorized more recently.
+pub async fn save_refresh_token(
+    pool: &PgPool,
+    provider: Provider,
+    owner_id: i64,
+    user_id: i64,
+    grant: &OAuthGrant,
+    encryption_key: &str,
+) -> Result<Option<OAuthIntegration>> {
+    let encrypted_refresh_token = encrypt_token(&grant.refresh_token, encryption_key)?;
+
+    let integration = query_as!(
+        OAuthIntegration,
+        r#"
+        INSERT INTO oauth_integrations
+            (provider, owner_id, user_id, encrypted_refresh_token, account_id, granted_scopes,
+             authorized_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (provider, owner_id, user_id)
+        DO UPDATE SET
+            encrypted_refresh_token = $4,
+            account_id = $5,
+            granted_scopes = $6,
+            -- The cached access token may belong to another account
+            encrypted_access_token = NULL,
+            access_token_expires_at = NULL,
+            is_active = true,
+            deactivated_reason = NULL,
+            deactivated_at = NULL,
+            authorized_at = $7,
+            updated_at = NOW()
+        WHERE oauth_integrations.authorized_at < $7
+        RETURNING id, provider AS "provider: Provider", owner_id, user_id, account_id,
+                  granted_scopes, access_token_expires_at, is_active, deactivated_reason,
+                  deactivated_at, authorized_at, created_at, updated_at
+        "#,
+        provider as Provider,
+        owner_id,
+        user_id,
+        encrypted_refresh_token,
+        grant.account_id,
+        grant.granted_scopes,
+        grant.authorized_at,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(integration)
+}
+
+pub async fn save_access_token(
+    pool: &PgPool,
+    provider: Provider,
+    owner_id: i64,
+    user_id: i64,
+    access_token: &str,
+    expires_at: DateTime<Utc>,
+    encryption_key: &str,
+) -> Result<bool> {
+    let encrypted_access_token = encrypt_token(access_token, encryption_key)?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE oauth_integrations
+        SET encrypted_access_token = $4, access_token_expires_at = $5, updated_at = NOW()
+        WHERE provider = $1 AND owner_id = $2 AND user_id = $3 AND is_active = true
+        "#,
+        provider as Provider,
+        owner_id,
+        user_id,
+        encrypted_access_token,
+        expires_at,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn deactivate_integration(
+    pool: &PgPool,
+    provider: Provider,
+    owner_id: i64,
+    user_id: i64,
+    reason: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE oauth_integrations
+        SET is_active = false,
+            encrypted_access_token = NULL,
+            access_token_expires_at = NULL,
+            deactivated_reason = $4,
+            deactivated_at = NOW(),
+            updated_at = NOW()
+        WHERE provider = $1 AND owner_id = $2 AND user_id = $3
+        "#,
+        provider as Provider,
+        owner_id,
+        user_id,
+        reason
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
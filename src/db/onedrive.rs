@@ -1,19 +1,55 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use sqlx::{query_as, PgPool};
+use sqlx::{query_as, PgPool, Postgres, Transaction};
 
 use crate::db::encryption::{decrypt_token, encrypt_token};
-use crate::db::models::{OneDriveAccessToken, OneDriveIntegration, OneDriveRefreshToken};
+use crate::db::models::{
+    MicrosoftCloud, OneDriveAccessToken, OneDriveAuthMode, OneDriveIntegration,
+    OneDriveRefreshToken, TokenRefreshCandidate,
+};
 
-pub async fn get_integration(pool: &PgPool, owner_id: i64) -> Result<Option<OneDriveIntegration>> {
+pub async fn get_integration(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<OneDriveIntegration>> {
     let integration = query_as!(
         OneDriveIntegration,
         r#"
-        SELECT id, owner_id, user_id, access_token_expires_at, is_active, created_at, updated_at
+        SELECT id, owner_id, user_id, auth_mode AS "auth_mode: OneDriveAuthMode", tenant_id,
+               cloud AS "cloud: MicrosoftCloud", drive_user, drive_site_id, account_upn, drive_id,
+               drive_type, requested_scopes, granted_scopes, access_token_expires_at, is_active,
+               is_default, deactivated_reason, deactivated_at, authorized_at, created_at, updated_at
         FROM onedrive_integrations
-        WHERE owner_id = $1 AND is_active = true
+        WHERE owner_id = $1 AND user_id = $2 AND is_active = true
         "#,
-        owner_id
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(integration)
+}
+
+/// Get a user's integration if it has been deactivated
+pub async fn get_deactivated_integration(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<OneDriveIntegration>> {
+    let integration = query_as!(
+        OneDriveIntegration,
+        r#"
+        SELECT id, owner_id, user_id, auth_mode AS "auth_mode: OneDriveAuthMode", tenant_id,
+               cloud AS "cloud: MicrosoftCloud", drive_user, drive_site_id, account_upn, drive_id,
+               drive_type, requested_scopes, granted_scopes, access_token_expires_at, is_active,
+               is_default, deactivated_reason, deactivated_at, authorized_at, created_at, updated_at
+        FROM onedrive_integrations
+        WHERE owner_id = $1 AND user_id = $2 AND is_active = false
+        "#,
+        owner_id,
+        user_id
     )
     .fetch_optional(pool)
     .await?;
@@ -24,16 +60,21 @@ pub async fn get_integration(pool: &PgPool, owner_id: i64) -> Result<Option<OneD
 pub async fn get_refresh_token(
     pool: &PgPool,
     owner_id: i64,
+    user_id: i64,
     encryption_key: &str,
 ) -> Result<Option<OneDriveRefreshToken>> {
     // Fetch the encrypted refresh token
     let record = sqlx::query!(
         r#"
-        SELECT encrypted_refresh_token
+        SELECT encrypted_refresh_token AS "encrypted_refresh_token!"
         FROM onedrive_integrations
-        WHERE owner_id = $1 AND is_active = true
+        WHERE owner_id = $1
+          AND user_id = $2
+          AND is_active = true
+          AND encrypted_refresh_token IS NOT NULL
         "#,
-        owner_id
+        owner_id,
+        user_id
     )
     .fetch_optional(pool)
     .await?;
@@ -52,6 +93,7 @@ pub async fn get_refresh_token(
 pub async fn get_access_token(
     pool: &PgPool,
     owner_id: i64,
+    user_id: i64,
     encryption_key: &str,
 ) -> Result<Option<OneDriveAccessToken>> {
     // Fetch the encrypted access token
@@ -60,11 +102,13 @@ pub async fn get_access_token(
         SELECT encrypted_access_token, access_token_expires_at
         FROM onedrive_integrations
         WHERE owner_id = $1
+          AND user_id = $2
           AND is_active = true
           AND encrypted_access_token IS NOT NULL
           AND access_token_expires_at > NOW()
         "#,
-        owner_id
+        owner_id,
+        user_id
     )
     .fetch_optional(pool)
     .await?;
@@ -84,13 +128,18 @@ pub async fn get_access_token(
     }
 }
 
+/// Save the refresh token from an authorization made at `authorized_at`. Returns `None` without
+/// changing anything if the integration was already authorized more recently. An owner's first
+/// integration becomes its default.
 pub async fn save_refresh_token(
     pool: &PgPool,
     owner_id: i64,
     user_id: i64,
     refresh_token: &str,
+    requested_scopes: Option<&str>,
+    authorized_at: DateTime<Utc>,
     encryption_key: &str,
-) -> Result<OneDriveIntegration> {
+) -> Result<Option<OneDriveIntegration>> {
     // Encrypt the refresh token
     let encrypted_refresh_token = encrypt_token(refresh_token, encryption_key)?;
 
@@ -99,32 +148,207 @@ pub async fn save_refresh_token(
         OneDriveIntegration,
         r#"
         INSERT INTO onedrive_integrations
-            (owner_id, user_id, encrypted_refresh_token, is_active)
+            (owner_id, user_id, encrypted_refresh_token, requested_scopes, is_active, is_default,
+             token_refreshed_at, authorized_at)
         VALUES
-            ($1, $2, $3, true)
-        ON CONFLICT (owner_id)
+            ($1, $2, $3, $5, true,
+             NOT EXISTS (SELECT 1 FROM onedrive_integrations WHERE owner_id = $1 AND is_default),
+             NOW(), $4)
+        ON CONFLICT (owner_id, user_id)
         DO UPDATE SET
-            user_id = $2,
+            auth_mode = 'delegated',
+            drive_user = NULL,
+            drive_site_id = NULL,
             encrypted_refresh_token = $3,
+            requested_scopes = $5,
+            -- An app-only access token must not be used on behalf of the user
+            encrypted_access_token = CASE
+                WHEN onedrive_integrations.auth_mode = 'delegated'
+                THEN onedrive_integrations.encrypted_access_token
+            END,
+            access_token_expires_at = CASE
+                WHEN onedrive_integrations.auth_mode = 'delegated'
+                THEN onedrive_integrations.access_token_expires_at
+            END,
             is_active = true,
+            -- Reconnecting becomes the default if the owner was left without one
+            is_default = onedrive_integrations.is_default OR NOT EXISTS (
+                SELECT 1 FROM onedrive_integrations WHERE owner_id = $1 AND is_default
+            ),
+            deactivated_reason = NULL,
+            deactivated_at = NULL,
+            token_refreshed_at = NOW(),
+            authorized_at = $4,
             updated_at = NOW()
-        RETURNING id, owner_id, user_id, access_token_expires_at, is_active, created_at, updated_at
+        WHERE onedrive_integrations.authorized_at IS NULL
+            OR onedrive_integrations.authorized_at < $4
+        RETURNING id, owner_id, user_id, auth_mode AS "auth_mode: OneDriveAuthMode", tenant_id,
+                  cloud AS "cloud: MicrosoftCloud", drive_user, drive_site_id, account_upn, drive_id,
+                  drive_type, requested_scopes, granted_scopes, access_token_expires_at, is_active,
+                  is_default, deactivated_reason, deactivated_at, authorized_at, created_at, updated_at
         "#,
         owner_id,
         user_id,
         encrypted_refresh_token,
+        authorized_at,
+        requested_scopes,
     )
-    .fetch_one(pool)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(integration)
+}
+
+/// Replace the refresh token of a user's active integration with one Microsoft rotated
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    refresh_token: &str,
+    encryption_key: &str,
+) -> Result<bool> {
+    let encrypted_refresh_token = encrypt_token(refresh_token, encryption_key)?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE onedrive_integrations
+        SET encrypted_refresh_token = $3,
+            token_refreshed_at = NOW(),
+            updated_at = NOW()
+        WHERE owner_id = $1 AND user_id = $2 AND is_active = true
+        "#,
+        owner_id,
+        user_id,
+        encrypted_refresh_token,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Create or replace a user's integration with an app-only one for a tenant that granted
+/// admin consent. App-only integrations have no refresh token. Returns `None` without changing
+/// anything if the integration was already authorized more recently than `authorized_at`.
+pub async fn save_tenant_integration(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    tenant_id: &str,
+    drive_user: Option<&str>,
+    drive_site_id: Option<&str>,
+    authorized_at: DateTime<Utc>,
+) -> Result<Option<OneDriveIntegration>> {
+    let integration = sqlx::query_as!(
+        OneDriveIntegration,
+        r#"
+        INSERT INTO onedrive_integrations
+            (owner_id, user_id, auth_mode, tenant_id, drive_user, drive_site_id, is_active,
+             is_default, token_refreshed_at, authorized_at)
+        VALUES
+            ($1, $2, 'app_only', $3, $4, $5, true,
+             NOT EXISTS (SELECT 1 FROM onedrive_integrations WHERE owner_id = $1 AND is_default),
+             NOW(), $6)
+        ON CONFLICT (owner_id, user_id)
+        DO UPDATE SET
+            auth_mode = 'app_only',
+            tenant_id = $3,
+            drive_user = $4,
+            drive_site_id = $5,
+            encrypted_refresh_token = NULL,
+            requested_scopes = NULL,
+            encrypted_access_token = NULL,
+            access_token_expires_at = NULL,
+            is_active = true,
+            -- Reconnecting becomes the default if the owner was left without one
+            is_default = onedrive_integrations.is_default OR NOT EXISTS (
+                SELECT 1 FROM onedrive_integrations WHERE owner_id = $1 AND is_default
+            ),
+            deactivated_reason = NULL,
+            deactivated_at = NULL,
+            token_refreshed_at = NOW(),
+            authorized_at = $6,
+            updated_at = NOW()
+        WHERE onedrive_integrations.authorized_at IS NULL
+            OR onedrive_integrations.authorized_at < $6
+        RETURNING id, owner_id, user_id, auth_mode AS "auth_mode: OneDriveAuthMode", tenant_id,
+                  cloud AS "cloud: MicrosoftCloud", drive_user, drive_site_id, account_upn, drive_id,
+                  drive_type, requested_scopes, granted_scopes, access_token_expires_at, is_active,
+                  is_default, deactivated_reason, deactivated_at, authorized_at, created_at, updated_at
+        "#,
+        owner_id,
+        user_id,
+        tenant_id,
+        drive_user,
+        drive_site_id,
+        authorized_at,
+    )
+    .fetch_optional(pool)
     .await?;
 
     Ok(integration)
 }
 
+/// Set the tenant and Microsoft national cloud a user's integration uses instead of the
+/// deployment's defaults. `None` falls back to the default.
+pub async fn save_endpoint_overrides(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    tenant_id: Option<&str>,
+    cloud: Option<MicrosoftCloud>,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE onedrive_integrations
+        SET tenant_id = $3, cloud = $4, updated_at = NOW()
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id,
+        tenant_id,
+        cloud as Option<MicrosoftCloud>,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record the Microsoft account and drive behind a user's integration
+pub async fn save_account_details(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    account_upn: Option<&str>,
+    drive_id: &str,
+    drive_type: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE onedrive_integrations
+        SET account_upn = $3, drive_id = $4, drive_type = $5, updated_at = NOW()
+        WHERE owner_id = $1 AND user_id = $2 AND is_active = true
+        "#,
+        owner_id,
+        user_id,
+        account_upn,
+        drive_id,
+        drive_type,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
 pub async fn save_access_token(
     pool: &PgPool,
     owner_id: i64,
+    user_id: i64,
     access_token: &str,
     expires_at: DateTime<Utc>,
+    granted_scopes: Option<&str>,
     encryption_key: &str,
 ) -> Result<OneDriveIntegration> {
     // Encrypt the access token
@@ -136,15 +360,22 @@ pub async fn save_access_token(
         r#"
         UPDATE onedrive_integrations
         SET
-            encrypted_access_token = $2,
-            access_token_expires_at = $3,
+            encrypted_access_token = $3,
+            access_token_expires_at = $4,
+            granted_scopes = COALESCE($5, granted_scopes),
+            token_refreshed_at = NOW(),
             updated_at = NOW()
-        WHERE owner_id = $1 AND is_active = true
-        RETURNING id, owner_id, user_id, access_token_expires_at, is_active, created_at, updated_at
+        WHERE owner_id = $1 AND user_id = $2 AND is_active = true
+        RETURNING id, owner_id, user_id, auth_mode AS "auth_mode: OneDriveAuthMode", tenant_id,
+                  cloud AS "cloud: MicrosoftCloud", drive_user, drive_site_id, account_upn, drive_id,
+                  drive_type, requested_scopes, granted_scopes, access_token_expires_at, is_active,
+                  is_default, deactivated_reason, deactivated_at, authorized_at, created_at, updated_at
         "#,
         owner_id,
+        user_id,
         encrypted_access_token,
         expires_at,
+        granted_scopes,
     )
     .fetch_one(pool)
     .await?;
@@ -152,14 +383,72 @@ pub async fn save_access_token(
     Ok(integration)
 }
 
-pub async fn deactivate_integration(pool: &PgPool, owner_id: i64) -> Result<bool> {
+/// Find integrations whose access token expires before `expiring_before`, or whose refresh
+/// token has not been exchanged since `idle_since` and risks hitting Microsoft's inactivity expiry
+pub async fn get_token_refresh_candidates(
+    pool: &PgPool,
+    expiring_before: DateTime<Utc>,
+    idle_since: DateTime<Utc>,
+) -> Result<Vec<TokenRefreshCandidate>> {
+    let candidates = query_as!(
+        TokenRefreshCandidate,
+        r#"
+        SELECT owner_id, user_id,
+               COALESCE(token_refreshed_at, updated_at) < $2 AS "refresh_token_idle!",
+               COALESCE(token_refreshed_at, updated_at) AS "token_refreshed_at!"
+        FROM onedrive_integrations
+        WHERE is_active = true
+          AND (
+            (access_token_expires_at > NOW() AND access_token_expires_at < $1)
+            OR COALESCE(token_refreshed_at, updated_at) < $2
+          )
+        ORDER BY owner_id, user_id
+        "#,
+        expiring_before,
+        idle_since,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(candidates)
+}
+
+/// When an integration's refresh token was last exchanged
+pub async fn get_token_refreshed_at(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<DateTime<Utc>>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT COALESCE(token_refreshed_at, updated_at) AS "token_refreshed_at!"
+        FROM onedrive_integrations
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|record| record.token_refreshed_at))
+}
+
+pub async fn deactivate_integration(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    reason: &str,
+) -> Result<bool> {
     let result = sqlx::query!(
         r#"
         UPDATE onedrive_integrations
-        SET is_active = false, updated_at = NOW()
-        WHERE owner_id = $1
+        SET is_active = false, deactivated_reason = $3, deactivated_at = NOW(), updated_at = NOW()
+        WHERE owner_id = $1 AND user_id = $2
         "#,
-        owner_id
+        owner_id,
+        user_id,
+        reason
     )
     .execute(pool)
     .await?;
@@ -167,3 +456,139 @@ pub async fn deactivate_integration(pool: &PgPool, owner_id: i64) -> Result<bool
     Ok(result.rows_affected() > 0)
 }
 
+/// Deactivate a user's integration at their request, unless it was authorized after
+/// `requested_at`. The stored tokens are wiped and the time is recorded so file syncs queued
+/// before the disconnect can be cancelled. If it was the owner's default, the default passes to
+/// their oldest remaining active integration. Returns whether one was disconnected.
+pub async fn disconnect_integration(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    requested_at: DateTime<Utc>,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let disconnected = sqlx::query!(
+        r#"
+        UPDATE onedrive_integrations
+        SET
+            is_active = false,
+            encrypted_refresh_token = NULL,
+            encrypted_access_token = NULL,
+            access_token_expires_at = NULL,
+            deactivated_reason = 'Disconnected by user',
+            deactivated_at = NOW(),
+            disconnected_at = NOW(),
+            updated_at = NOW()
+        WHERE owner_id = $1 AND user_id = $2
+          AND (authorized_at IS NULL OR authorized_at < $3)
+        RETURNING is_default
+        "#,
+        owner_id,
+        user_id,
+        requested_at
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(disconnected) = disconnected else {
+        return Ok(false);
+    };
+
+    // Cleared before promoting another integration, since only one default may exist at a time
+    if disconnected.is_default {
+        sqlx::query!(
+            r#"
+            UPDATE onedrive_integrations
+            SET is_default = false
+            WHERE owner_id = $1 AND user_id = $2
+            "#,
+            owner_id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE onedrive_integrations
+            SET is_default = true
+            WHERE id = (
+                SELECT id FROM onedrive_integrations
+                WHERE owner_id = $1 AND is_active = true
+                ORDER BY created_at
+                LIMIT 1
+            )
+            "#,
+            owner_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Get when a user last disconnected OneDrive, if ever
+pub async fn get_disconnected_at(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<DateTime<Utc>>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT disconnected_at
+        FROM onedrive_integrations
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.and_then(|record| record.disconnected_at))
+}
+
+/// Get the user whose integration is used for an owner's messages that don't name a user
+pub async fn get_default_user_id(pool: &PgPool, owner_id: i64) -> Result<Option<i64>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM onedrive_integrations
+        WHERE owner_id = $1 AND is_default
+        "#,
+        owner_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|record| record.user_id))
+}
+
+/// Take a transaction-scoped advisory lock that serializes token refreshes for a user's
+/// integration across every worker sharing this database. The lock is released when the
+/// returned transaction is committed or dropped.
+pub async fn lock_token_refresh(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Transaction<'static, Postgres>> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        SELECT pg_advisory_xact_lock(
+            hashtextextended(format('onedrive_token_refresh:%s:%s', $1::BIGINT, $2::BIGINT), 0)
+        )
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(tx)
+}
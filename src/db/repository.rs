@@ -0,0 +1,757 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::db;
+use crate::db::models::{
+    MicrosoftCloud, OAuthAccessToken, OAuthGrant, OAuthIntegration, OneDriveAccessToken,
+    OneDriveIntegration, OneDriveRefreshToken, OneDriveWatch, Provider, SftpConnection,
+    SftpCredentials, SyncJob, SyncJobStatus, TokenRefreshCandidate, WatchedFolder,
+    WebDavConnection, WebDavCredentials,
+};
+
+/// Storage of OneDrive integrations and their tokens. Tokens are passed in and out in plain
+/// text; implementations that persist them are responsible for encrypting them.
+#[async_trait]
+pub trait IntegrationRepository: Send + Sync {
+    /// Get a user's active integration
+    async fn get_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveIntegration>>;
+
+    /// Get a user's integration if it has been deactivated
+    async fn get_deactivated_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveIntegration>>;
+
+    async fn get_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveRefreshToken>>;
+
+    /// Get the cached access token of a user's active integration unless it has expired
+    async fn get_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveAccessToken>>;
+
+    /// Save the refresh token from an authorization made at `authorized_at`. Returns `None`
+    /// without changing anything if the integration was already authorized more recently.
+    /// An owner's first integration becomes its default.
+    async fn save_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refresh_token: &str,
+        requested_scopes: Option<&str>,
+        authorized_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveIntegration>>;
+
+    /// Replace the refresh token of a user's active integration with one Microsoft rotated
+    async fn rotate_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refresh_token: &str,
+    ) -> Result<bool>;
+
+    /// Create or replace a user's integration with an app-only one. Returns `None` without
+    /// changing anything if the integration was already authorized more recently.
+    async fn save_tenant_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        tenant_id: &str,
+        drive_user: Option<&str>,
+        drive_site_id: Option<&str>,
+        authorized_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveIntegration>>;
+
+    /// Set the tenant and Microsoft endpoints a user's integration uses instead of the
+    /// deployment's defaults
+    async fn save_endpoint_overrides(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        tenant_id: Option<&str>,
+        cloud: Option<MicrosoftCloud>,
+    ) -> Result<bool>;
+
+    /// Record the Microsoft account and drive behind a user's integration
+    async fn save_account_details(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        account_upn: Option<&str>,
+        drive_id: &str,
+        drive_type: &str,
+    ) -> Result<bool>;
+
+    async fn save_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        access_token: &str,
+        expires_at: DateTime<Utc>,
+        granted_scopes: Option<&str>,
+    ) -> Result<OneDriveIntegration>;
+
+    /// Find integrations whose access token expires before `expiring_before`, or whose refresh
+    /// token has not been exchanged since `idle_since`
+    async fn get_token_refresh_candidates(
+        &self,
+        expiring_before: DateTime<Utc>,
+        idle_since: DateTime<Utc>,
+    ) -> Result<Vec<TokenRefreshCandidate>>;
+
+    /// When an integration's refresh token was last exchanged
+    async fn get_token_refreshed_at(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>>;
+
+    async fn deactivate_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool>;
+
+    /// Deactivate a user's integration at their request and wipe its tokens, unless it was
+    /// authorized after `requested_at`. If it was the owner's default, the default passes to
+    /// their oldest remaining active integration. Returns whether one was disconnected.
+    async fn disconnect_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        requested_at: DateTime<Utc>,
+    ) -> Result<bool>;
+
+    /// Get when a user last disconnected OneDrive, if ever
+    async fn get_disconnected_at(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>>;
+
+    /// Get the user whose integration is used for an owner's messages that don't name a user
+    async fn get_default_user_id(&self, owner_id: i64) -> Result<Option<i64>>;
+
+    /// Serialize token refreshes for a user's integration across every worker sharing this
+    /// repository, until the returned lock is released or dropped
+    async fn lock_token_refresh(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Box<dyn TokenRefreshLock>>;
+}
+
+/// A lock taken with `IntegrationRepository::lock_token_refresh`
+#[async_trait]
+pub trait TokenRefreshLock: Send {
+    async fn release(self: Box<Self>) -> Result<()>;
+}
+
+/// Storage of file sync jobs
+#[async_trait]
+pub trait JobRepository: Send + Sync {
+    /// Record a file sync that is about to be attempted
+    async fn create_sync_job(
+        &self,
+        owner_id: i64,
+        user_id: Option<i64>,
+        provider: Provider,
+        source: &str,
+        destination: &str,
+        requested_at: DateTime<Utc>,
+    ) -> Result<SyncJob>;
+
+    /// Record the outcome of a file sync
+    async fn finish_sync_job(
+        &self,
+        id: i64,
+        status: SyncJobStatus,
+        drive_item_id: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<bool>;
+}
+
+/// Storage of Google Drive and Dropbox integrations and their tokens, which like OneDrive's are
+/// passed in and out in plain text
+#[async_trait]
+pub trait OAuthRepository: Send + Sync {
+    /// Get a user's integration with a provider, whether or not it is still active
+    async fn get_oauth_integration(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OAuthIntegration>>;
+
+    async fn get_oauth_refresh_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<String>>;
+
+    /// Get the cached access token of a user's active integration unless it has expired
+    async fn get_oauth_access_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OAuthAccessToken>>;
+
+    /// Save the refresh token of a grant. Returns `None` without changing anything if the
+    /// integration was already authorized more recently.
+    async fn save_oauth_refresh_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        grant: &OAuthGrant,
+    ) -> Result<Option<OAuthIntegration>>;
+
+    async fn save_oauth_access_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        access_token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool>;
+
+    async fn deactivate_oauth_integration(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool>;
+}
+
+/// Storage of users' WebDAV servers. Passwords are passed in and out in plain text;
+/// implementations that persist them are responsible for encrypting them.
+#[async_trait]
+pub trait WebDavRepository: Send + Sync {
+    async fn get_webdav_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WebDavConnection>>;
+
+    async fn get_webdav_credentials(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WebDavCredentials>>;
+
+    /// Save the credentials a user gave at `connected_at`. Returns `None` without changing
+    /// anything if the connection was already saved more recently.
+    async fn save_webdav_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        credentials: &WebDavCredentials,
+        connected_at: DateTime<Utc>,
+    ) -> Result<Option<WebDavConnection>>;
+}
+
+/// Storage of users' SFTP servers. Private keys are passed in and out in plain text;
+/// implementations that persist them are responsible for encrypting them.
+#[async_trait]
+pub trait SftpRepository: Send + Sync {
+    async fn get_sftp_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<SftpConnection>>;
+
+    async fn get_sftp_credentials(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<SftpCredentials>>;
+
+    /// Save the credentials a user gave at `connected_at`. Returns `None` without changing
+    /// anything if the connection was already saved more recently.
+    async fn save_sftp_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        credentials: &SftpCredentials,
+        connected_at: DateTime<Utc>,
+    ) -> Result<Option<SftpConnection>>;
+}
+
+/// Storage of watched OneDrive folders and where their delta queries resume
+#[async_trait]
+pub trait WatchRepository: Send + Sync {
+    /// Claim the watches whose OneDrive integration is active and that weren't polled since
+    /// `due_before`, marking them polled now. A watch is only claimed by one caller at a time.
+    async fn claim_due_onedrive_watches(
+        &self,
+        due_before: DateTime<Utc>,
+    ) -> Result<Vec<OneDriveWatch>>;
+
+    /// Save the folder a user asked to watch at `registered_at`, starting over without a delta
+    /// link. Returns `None` without changing anything if a watch was registered more recently.
+    async fn save_onedrive_watch(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        folder: &WatchedFolder,
+        registered_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveWatch>>;
+
+    /// Record where a watch's next delta query resumes. Returns `false` if the watch was
+    /// registered again since `registered_at`.
+    async fn save_onedrive_delta_link(
+        &self,
+        watch_id: i32,
+        registered_at: DateTime<Utc>,
+        delta_link: &str,
+    ) -> Result<bool>;
+}
+
+/// Repository backed by Postgres, encrypting tokens at rest
+pub struct PgRepository {
+    pool: PgPool,
+    encryption_key: String,
+}
+
+impl PgRepository {
+    pub fn new(pool: PgPool, encryption_key: String) -> Self {
+        Self { pool, encryption_key }
+    }
+}
+
+#[async_trait]
+impl IntegrationRepository for PgRepository {
+    async fn get_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveIntegration>> {
+        db::onedrive::get_integration(&self.pool, owner_id, user_id).await
+    }
+
+    async fn get_deactivated_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveIntegration>> {
+        db::onedrive::get_deactivated_integration(&self.pool, owner_id, user_id).await
+    }
+
+    async fn get_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveRefreshToken>> {
+        db::onedrive::get_refresh_token(&self.pool, owner_id, user_id, &self.encryption_key).await
+    }
+
+    async fn get_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OneDriveAccessToken>> {
+        db::onedrive::get_access_token(&self.pool, owner_id, user_id, &self.encryption_key).await
+    }
+
+    async fn save_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refresh_token: &str,
+        requested_scopes: Option<&str>,
+        authorized_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveIntegration>> {
+        db::onedrive::save_refresh_token(
+            &self.pool,
+            owner_id,
+            user_id,
+            refresh_token,
+            requested_scopes,
+            authorized_at,
+            &self.encryption_key,
+        )
+        .await
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refresh_token: &str,
+    ) -> Result<bool> {
+        db::onedrive::rotate_refresh_token(
+            &self.pool,
+            owner_id,
+            user_id,
+            refresh_token,
+            &self.encryption_key,
+        )
+        .await
+    }
+
+    async fn save_tenant_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        tenant_id: &str,
+        drive_user: Option<&str>,
+        drive_site_id: Option<&str>,
+        authorized_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveIntegration>> {
+        db::onedrive::save_tenant_integration(
+            &self.pool,
+            owner_id,
+            user_id,
+            tenant_id,
+            drive_user,
+            drive_site_id,
+            authorized_at,
+        )
+        .await
+    }
+
+    async fn save_endpoint_overrides(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        tenant_id: Option<&str>,
+        cloud: Option<MicrosoftCloud>,
+    ) -> Result<bool> {
+        db::onedrive::save_endpoint_overrides(&self.pool, owner_id, user_id, tenant_id, cloud).await
+    }
+
+    async fn save_account_details(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        account_upn: Option<&str>,
+        drive_id: &str,
+        drive_type: &str,
+    ) -> Result<bool> {
+        db::onedrive::save_account_details(
+            &self.pool,
+            owner_id,
+            user_id,
+            account_upn,
+            drive_id,
+            drive_type,
+        )
+        .await
+    }
+
+    async fn save_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        access_token: &str,
+        expires_at: DateTime<Utc>,
+        granted_scopes: Option<&str>,
+    ) -> Result<OneDriveIntegration> {
+        db::onedrive::save_access_token(
+            &self.pool,
+            owner_id,
+            user_id,
+            access_token,
+            expires_at,
+            granted_scopes,
+            &self.encryption_key,
+        )
+        .await
+    }
+
+    async fn get_token_refresh_candidates(
+        &self,
+        expiring_before: DateTime<Utc>,
+        idle_since: DateTime<Utc>,
+    ) -> Result<Vec<TokenRefreshCandidate>> {
+        db::onedrive::get_token_refresh_candidates(&self.pool, expiring_before, idle_since).await
+    }
+
+    async fn get_token_refreshed_at(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>> {
+        db::onedrive::get_token_refreshed_at(&self.pool, owner_id, user_id).await
+    }
+
+    async fn deactivate_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        db::onedrive::deactivate_integration(&self.pool, owner_id, user_id, reason).await
+    }
+
+    async fn disconnect_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        requested_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        db::onedrive::disconnect_integration(&self.pool, owner_id, user_id, requested_at).await
+    }
+
+    async fn get_disconnected_at(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>> {
+        db::onedrive::get_disconnected_at(&self.pool, owner_id, user_id).await
+    }
+
+    async fn get_default_user_id(&self, owner_id: i64) -> Result<Option<i64>> {
+        db::onedrive::get_default_user_id(&self.pool, owner_id).await
+    }
+
+    async fn lock_token_refresh(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Box<dyn TokenRefreshLock>> {
+        let tx = db::onedrive::lock_token_refresh(&self.pool, owner_id, user_id).await?;
+        Ok(Box::new(tx))
+    }
+}
+
+/// The advisory lock is held until the transaction ends
+#[async_trait]
+impl TokenRefreshLock for Transaction<'static, Postgres> {
+    async fn release(self: Box<Self>) -> Result<()> {
+        self.commit().await.context("Failed to release token refresh lock")
+    }
+}
+
+#[async_trait]
+impl JobRepository for PgRepository {
+    async fn create_sync_job(
+        &self,
+        owner_id: i64,
+        user_id: Option<i64>,
+        provider: Provider,
+        source: &str,
+        destination: &str,
+        requested_at: DateTime<Utc>,
+    ) -> Result<SyncJob> {
+        db::jobs::create_sync_job(
+            &self.pool,
+            owner_id,
+            user_id,
+            provider,
+            source,
+            destination,
+            requested_at,
+        )
+        .await
+    }
+
+    async fn finish_sync_job(
+        &self,
+        id: i64,
+        status: SyncJobStatus,
+        drive_item_id: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<bool> {
+        db::jobs::finish_sync_job(&self.pool, id, status, drive_item_id, error).await
+    }
+}
+
+#[async_trait]
+impl OAuthRepository for PgRepository {
+    async fn get_oauth_integration(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OAuthIntegration>> {
+        db::oauth::get_integration(&self.pool, provider, owner_id, user_id).await
+    }
+
+    async fn get_oauth_refresh_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<String>> {
+        db::oauth::get_refresh_token(&self.pool, provider, owner_id, user_id, &self.encryption_key)
+            .await
+    }
+
+    async fn get_oauth_access_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OAuthAccessToken>> {
+        db::oauth::get_access_token(&self.pool, provider, owner_id, user_id, &self.encryption_key)
+            .await
+    }
+
+    async fn save_oauth_refresh_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        grant: &OAuthGrant,
+    ) -> Result<Option<OAuthIntegration>> {
+        db::oauth::save_refresh_token(
+            &self.pool,
+            provider,
+            owner_id,
+            user_id,
+            grant,
+            &self.encryption_key,
+        )
+        .await
+    }
+
+    async fn save_oauth_access_token(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        access_token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        db::oauth::save_access_token(
+            &self.pool,
+            provider,
+            owner_id,
+            user_id,
+            access_token,
+            expires_at,
+            &self.encryption_key,
+        )
+        .await
+    }
+
+    async fn deactivate_oauth_integration(
+        &self,
+        provider: Provider,
+        owner_id: i64,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool> {
+        db::oauth::deactivate_integration(&self.pool, provider, owner_id, user_id, reason).await
+    }
+}
+
+#[async_trait]
+impl WebDavRepository for PgRepository {
+    async fn get_webdav_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WebDavConnection>> {
+        db::webdav::get_connection(&self.pool, owner_id, user_id).await
+    }
+
+    async fn get_webdav_credentials(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WebDavCredentials>> {
+        db::webdav::get_credentials(&self.pool, owner_id, user_id, &self.encryption_key).await
+    }
+
+    async fn save_webdav_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        credentials: &WebDavCredentials,
+        connected_at: DateTime<Utc>,
+    ) -> Result<Option<WebDavConnection>> {
+        db::webdav::save_connection(
+            &self.pool,
+            owner_id,
+            user_id,
+            credentials,
+            connected_at,
+            &self.encryption_key,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl SftpRepository for PgRepository {
+    async fn get_sftp_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<SftpConnection>> {
+        db::sftp::get_connection(&self.pool, owner_id, user_id).await
+    }
+
+    async fn get_sftp_credentials(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<Option<SftpCredentials>> {
+        db::sftp::get_credentials(&self.pool, owner_id, user_id, &self.encryption_key).await
+    }
+
+    async fn save_sftp_connection(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        credentials: &SftpCredentials,
+        connected_at: DateTime<Utc>,
+    ) -> Result<Option<SftpConnection>> {
+        db::sftp::save_connection(
+            &self.pool,
+            owner_id,
+            user_id,
+            credentials,
+            connected_at,
+            &self.encryption_key,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl WatchRepository for PgRepository {
+    async fn claim_due_onedrive_watches(
+        &self,
+        due_before: DateTime<Utc>,
+    ) -> Result<Vec<OneDriveWatch>> {
+        db::watches::claim_due_watches(&self.pool, due_before).await
+    }
+
+    async fn save_onedrive_watch(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        folder: &WatchedFolder,
+        registered_at: DateTime<Utc>,
+    ) -> Result<Option<OneDriveWatch>> {
+        db::watches::save_watch(&self.pool, owner_id, user_id, folder, registered_at).await
+    }
+
+    async fn save_onedrive_delta_link(
+        &self,
+        watch_id: i32,
+        registered_at: DateTime<Utc>,
+        delta_link: &str,
+    ) -> Result<bool> {
+        db::watches::save_delta_link(&self.pool, watch_id, registered_at, delta_link).await
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query_as, PgPool};
+
+use crate::db::encryption::{decrypt_token, encrypt_token};
+use crate::db::models::{SftpConnection, SftpCredentials};
+
+pub async fn get_connection(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<SftpConnection>> {
+    let connection = query_as!(
+        SftpConnection,
+        r#"
+        SELECT id, owner_id, user_id, host, port, username, host_key, root, connected_at,
+               created_at, updated_at
+        FROM sftp_connections
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(connection)
+}
+
+pub async fn get_credentials(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    encryption_key: &str,
+) -> Result<Option<SftpCredentials>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT host, port, username, encrypted_private_key, host_key, root
+        FROM sftp_connections
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match record {
+        Some(record) => Ok(Some(SftpCredentials {
+            host: record.host,
+            port: record.port,
+            username: record.username,
+            private_key: decrypt_token(&record.encrypted_private_key, encryption_key)?,
+            host_key: record.host_key,
+            root: record.root,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Save the credentials a user gave at `connected_at`. Returns `None` without changing anything
+/// if the connection was already saved more recently.
+pub async fn save_connection(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    credentials: &SftpCredentials,
+    connected_at: DateTime<Utc>,
+    encryption_key: &str,
+) -> Result<Option<SftpConnection>> {
+    let encrypted_private_key = encrypt_token(&credentials.private_key, encryption_key)?;
+
+    let connection = query_as!(
+        SftpConnection,
+        r#"
+        INSERT INTO sftp_connections
+            (owner_id, user_id, host, port, username, encrypted_private_key, host_key, root,
+             connected_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (owner_id, user_id)
+        DO UPDATE SET
+            host = $3,
+            port = $4,
+            username = $5,
+            encrypted_private_key = $6,
+            host_key = $7,
+            root = $8,
+            connected_at = $9,
+            updated_at = NOW()
+        WHERE sftp_connections.connected_at < $9
+        RETURNING id, owner_id, user_id, host, port, username, host_key, root, connected_at,
+                  created_at, updated_at
+        "#,
+        owner_id,
+        user_id,
+        credentials.host,
+        credentials.port,
+        credentials.username,
+        encrypted_private_key,
+        credentials.host_key,
+        credentials.root,
+        connected_at,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(connection)
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query_as, PgPool};
+
+use crate::db::models::{OneDriveWatch, WatchedFolder};
+
+/// Claim the watches whose integration is active and that weren't polled since `due_before`,
+/// marking them polled now. Rows another worker is claiming are skipped, so each watch is
+/// polled by one worker at a time.
+pub async fn claim_due_watches(
+    pool: &PgPool,
+    due_before: DateTime<Utc>,
+) -> Result<Vec<OneDriveWatch>> {
+    let watches = query_as!(
+        OneDriveWatch,
+        r#"
+        UPDATE onedrive_watches
+        SET polled_at = NOW(), updated_at = NOW()
+        WHERE id IN (
+            SELECT w.id
+            FROM onedrive_watches w
+            JOIN onedrive_integrations i ON i.owner_id = w.owner_id AND i.user_id = w.user_id
+            WHERE i.is_active = true AND (w.polled_at IS NULL OR w.polled_at < $1)
+            FOR UPDATE OF w SKIP LOCKED
+        )
+        RETURNING id, owner_id, user_id, folder_path, drive_id, bucket, prefix,
+                  delta_link, registered_at, polled_at, created_at, updated_at
+        "#,
+        due_before
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(watches)
+}
+
+/// Save the folder a user asked to watch at `registered_at`, starting over without a delta
+/// link. Returns `None` without changing anything if a watch was registered more recently.
+pub async fn save_watch(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    folder: &WatchedFolder,
+    registered_at: DateTime<Utc>,
+) -> Result<Option<OneDriveWatch>> {
+    let watch = query_as!(
+        OneDriveWatch,
+        r#"
+        INSERT INTO onedrive_watches
+            (owner_id, user_id, folder_path, drive_id, bucket, prefix, registered_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (owner_id, user_id)
+        DO UPDATE SET
+            folder_path = $3,
+            drive_id = $4,
+            bucket = $5,
+            prefix = $6,
+            registered_at = $7,
+            delta_link = NULL,
+            polled_at = NULL,
+            updated_at = NOW()
+        WHERE onedrive_watches.registered_at < $7
+        RETURNING id, owner_id, user_id, folder_path, drive_id, bucket, prefix,
+                  delta_link, registered_at, polled_at, created_at, updated_at
+        "#,
+        owner_id,
+        user_id,
+        folder.folder_path,
+        folder.drive_id,
+        folder.bucket,
+        folder.prefix,
+        registered_at,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(watch)
+}
+
+/// Record where a watch's next delta query resumes. Returns `false` if the watch was
+/// registered again since it was polled, whose delta link belongs to the old folder.
+pub async fn save_delta_link(
+    pool: &PgPool,
+    watch_id: i32,
+    registered_at: DateTime<Utc>,
+    delta_link: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE onedrive_watches
+        SET delta_link = $3, polled_at = NOW(), updated_at = NOW()
+        WHERE id = $1 AND registered_at = $2
+        "#,
+        watch_id,
+        registered_at,
+        delta_link
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query_as, PgPool};
+
+use crate::db::encryption::{decrypt_token, encrypt_token};
+use crate::db::models::{WebDavConnection, WebDavCredentials};
+
+pub async fn get_connection(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<Option<WebDavConnection>> {
+    let connection = query_as!(
+        WebDavConnection,
+        r#"
+        SELECT id, owner_id, user_id, url, username, connected_at, created_at, updated_at
+        FROM webdav_connections
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(connection)
+}
+
+pub async fn get_credentials(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    encryption_key: &str,
+) -> Result<Option<WebDavCredentials>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT url, username, encrypted_password
+        FROM webdav_connections
+        WHERE owner_id = $1 AND user_id = $2
+        "#,
+        owner_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match record {
+        Some(record) => Ok(Some(WebDavCredentials {
+            url: record.url,
+            username: record.username,
+            password: decrypt_token(&record.encrypted_password, encryption_key)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Save the credentials a user gave at `connected_at`. Returns `None` without changing anything
+/// if the connection was already saved more recently.
+pub async fn save_connection(
+    pool: &PgPool,
+    owner_id: i64,
+    user_id: i64,
+    credentials: &WebDavCredentials,
+    connected_at: DateTime<Utc>,
+    encryption_key: &str,
+) -> Result<Option<WebDavConnection>> {
+    let encrypted_password = encrypt_token(&credentials.password, encryption_key)?;
+
+    let connection = query_as!(
+        WebDavConnection,
+        r#"
+        INSERT INTO webdav_connections
+            (owner_id, user_id, url, username, encrypted_password, connected_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (owner_id, user_id)
+        DO UPDATE SET
+            url = $3,
+            username = $4,
+            encrypted_password = $5,
+            connected_at = $6,
+            updated_at = NOW()
+        WHERE webdav_connections.connected_at < $6
+        RETURNING id, owner_id, user_id, url, username, connected_at, created_at, updated_at
+        "#,
+        owner_id,
+        user_id,
+        credentials.url,
+        credentials.username,
+        encrypted_password,
+        connected_at,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(connection)
+}
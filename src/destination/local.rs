@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncWriteExt};
+
+use super::{Destination, DestinationPath};
+use crate::onedrive::drive::{DriveItem, DriveTarget};
+use crate::source::local::resolve_under;
+
+/// Writes files into a directory on the worker's filesystem, with a folder per owner. Meant for
+/// development and on-premises deployments that sync onto a shared volume.
+pub struct LocalDestination {
+    root: PathBuf,
+}
+
+impl LocalDestination {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Resolve a destination path to the owner's folder under the root
+    fn resolve(&self, item: &DestinationPath) -> Result<PathBuf> {
+        if item.target != DriveTarget::Default {
+            return Err(anyhow::anyhow!("Local destinations only have the default drive"));
+        }
+
+        resolve_under(&self.root.join(item.owner_id.to_string()), &item.path)
+    }
+
+    fn item(&self, full_path: &Path, metadata: &std::fs::Metadata) -> DriveItem {
+        let id = full_path.strip_prefix(&self.root).unwrap_or(full_path);
+        let name = full_path.file_name().map(|name| name.to_string_lossy().into_owned());
+
+        DriveItem {
+            id: id.to_string_lossy().into_owned(),
+            name: name.unwrap_or_default(),
+            size: metadata.is_file().then_some(metadata.len()),
+            web_url: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Destination for LocalDestination {
+    async fn ensure_folder(&self, folder: &DestinationPath) -> Result<()> {
+        let full_path = self.resolve(folder)?;
+
+        tokio::fs::create_dir_all(&full_path)
+            .await
+            .with_context(|| format!("Failed to create {}", full_path.display()))
+    }
+
+    /// Written to a temporary file first, so a partial upload never replaces an existing file
+    async fn upload(
+        &self,
+        file: &DestinationPath,
+        size: u64,
+        mut body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<DriveItem> {
+        let full_path = self.resolve(file)?;
+        let (Some(parent), Some(name)) = (full_path.parent(), full_path.file_name()) else {
+            return Err(anyhow::anyhow!("Local destination path {} has no file name", file.path));
+        };
+
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+
+        let partial_path = parent.join(format!(".{}.partial", name.to_string_lossy()));
+        let mut partial = tokio::fs::File::create(&partial_path)
+            .await
+            .with_context(|| format!("Failed to create {}", partial_path.display()))?;
+
+        let written = tokio::io::copy(&mut body, &mut partial).await;
+        let written = match written {
+            Ok(written) if written == size => partial.flush().await.map(|_| written),
+            Ok(written) => Err(std::io::Error::other(format!(
+                "File ended after {} of {} bytes",
+                written, size
+            ))),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&partial_path).await;
+            return Err(e).with_context(|| format!("Failed to write {}", full_path.display()));
+        }
+
+        tokio::fs::rename(&partial_path, &full_path)
+            .await
+            .with_context(|| format!("Failed to move file into {}", full_path.display()))?;
+
+        let metadata = tokio::fs::metadata(&full_path).await?;
+        Ok(self.item(&full_path, &metadata))
+    }
+
+    async fn stat(&self, item: &DestinationPath) -> Result<Option<DriveItem>> {
+        let full_path = self.resolve(item)?;
+
+        match tokio::fs::metadata(&full_path).await {
+            Ok(metadata) => Ok(Some(self.item(&full_path, &metadata))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", full_path.display())),
+        }
+    }
+
+    async fn delete(&self, item: &DestinationPath) -> Result<bool> {
+        let full_path = self.resolve(item)?;
+        if full_path == self.root.join(item.owner_id.to_string()) {
+            return Err(anyhow::anyhow!("The owner's local folder can't be deleted"));
+        }
+
+        let metadata = match tokio::fs::metadata(&full_path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", full_path.display()))
+            }
+        };
+
+        let removed = if metadata.is_dir() {
+            tokio::fs::remove_dir_all(&full_path).await
+        } else {
+            tokio::fs::remove_file(&full_path).await
+        };
+        removed.with_context(|| format!("Failed to delete {}", full_path.display()))?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(path: &str) -> DestinationPath {
+        DestinationPath {
+            owner_id: 123,
+            user_id: None,
+            target: DriveTarget::Default,
+            path: path.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_stat_and_delete() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let destination = LocalDestination::new(root.path());
+
+        let item = destination.upload(&at("/Reports/q1.csv"), 5, Box::new(&b"a,b,c"[..])).await?;
+        assert_eq!(item.id, "123/Reports/q1.csv");
+        assert_eq!(std::fs::read(root.path().join("123/Reports/q1.csv"))?, b"a,b,c");
+
+        // A short body leaves the existing file alone
+        let short = destination.upload(&at("/Reports/q1.csv"), 10, Box::new(&b"x"[..])).await;
+        assert!(short.is_err());
+        assert_eq!(destination.stat(&at("/Reports/q1.csv")).await?.unwrap().size, Some(5));
+        assert!(!root.path().join("123/Reports/.q1.csv.partial").exists());
+
+        assert!(destination.delete(&at("/Reports")).await?);
+        assert!(destination.stat(&at("/Reports/q1.csv")).await?.is_none());
+        assert!(!destination.delete(&at("/Reports")).await?);
+
+        for root in ["", "/", "//"] {
+            assert!(destination.delete(&at(root)).await.is_err());
+        }
+        assert!(root.path().join("123").exists());
+
+        assert!(destination.ensure_folder(&at("../456")).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+pub mod local;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::db::models::Provider;
+use crate::onedrive::drive::{DriveItem, DriveTarget};
+use local::LocalDestination;
+
+/// A slash-separated path in storage that an owner syncs files into
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DestinationPath {
+    pub owner_id: i64,
+    /// User whose connection is written through, for providers that have them
+    pub user_id: Option<i64>,
+    pub target: DriveTarget,
+    pub path: String,
+}
+
+/// Storage that synced files are written to
+#[async_trait]
+pub trait Destination: Send + Sync {
+    /// Create a folder and any missing parents, doing nothing if it already exists
+    async fn ensure_folder(&self, folder: &DestinationPath) -> Result<()>;
+
+    /// Write a file of `size` bytes, replacing any file already there. Missing parent folders
+    /// are created. Large files are streamed or sent in chunks, so they never have to be held
+    /// in memory whole.
+    async fn upload(
+        &self,
+        file: &DestinationPath,
+        size: u64,
+        body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<DriveItem>;
+
+    /// Look up a file or folder, or `None` if nothing is there
+    async fn stat(&self, item: &DestinationPath) -> Result<Option<DriveItem>>;
+
+    /// Delete a file, or a folder along with everything in it, returning whether anything was
+    /// there. The root of a destination can't be deleted.
+    async fn delete(&self, item: &DestinationPath) -> Result<bool>;
+}
+
+/// Read a file that should be `size` bytes into memory, failing if it's shorter or longer
+pub(crate) async fn read_whole<R: AsyncRead + Unpin>(body: R, size: u64) -> Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size as usize);
+    body.take(size + 1).read_to_end(&mut content).await.context("Failed to read file")?;
+
+    match content.len() as u64 {
+        read if read < size => Err(anyhow::anyhow!("File ended after {} of {} bytes", read, size)),
+        read if read > size => Err(anyhow::anyhow!("File is longer than {} bytes", size)),
+        _ => Ok(content),
+    }
+}
+
+/// Every destination the worker can write to
+pub struct Destinations {
+    pub onedrive: Arc<dyn Destination>,
+    /// Only available when a root directory is configured
+    pub local: Option<LocalDestination>,
+    /// Only available when Google OAuth client credentials are configured
+    pub google_drive: Option<Arc<dyn Destination>>,
+    /// Only available when a Dropbox app key and secret are configured
+    pub dropbox: Option<Arc<dyn Destination>>,
+    pub webdav: Arc<dyn Destination>,
+    pub sftp: Arc<dyn Destination>,
+}
+
+impl Destinations {
+    pub fn get(&self, provider: Provider) -> Result<&dyn Destination> {
+        match provider {
+            Provider::OneDrive => Ok(self.onedrive.as_ref()),
+            Provider::Local => {
+                Ok(self.local.as_ref().context("No LOCAL_DESTINATION_ROOT is configured")?)
+            }
+            Provider::GoogleDrive => Ok(self
+                .google_drive
+                .as_deref()
+                .context("No GOOGLE_CLIENT_ID and GOOGLE_CLIENT_SECRET are configured")?),
+            Provider::Dropbox => Ok(self
+                .dropbox
+                .as_deref()
+                .context("No DROPBOX_APP_KEY and DROPBOX_APP_SECRET are configured")?),
+            Provider::WebDav => Ok(self.webdav.as_ref()),
+            Provider::Sftp => Ok(self.sftp.as_ref()),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::DropboxClient;
+use crate::destination::{read_whole, Destination, DestinationPath};
+use crate::onedrive::drive::{DriveItem, DriveTarget};
+
+// Files up to this size are sent in one request, larger ones in chunks of it through an upload
+// session. Dropbox recommends chunks that are a multiple of 4 MiB.
+const UPLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    id: String,
+    name: String,
+    /// Only set for files
+    size: Option<u64>,
+}
+
+impl From<Metadata> for DriveItem {
+    fn from(metadata: Metadata) -> Self {
+        DriveItem { id: metadata.id, name: metadata.name, size: metadata.size, web_url: None }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadSessionStart {
+    session_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error_summary: String,
+}
+
+#[async_trait]
+impl Destination for DropboxClient {
+    /// Dropbox creates missing parents along with the folder
+    async fn ensure_folder(&self, folder: &DestinationPath) -> Result<()> {
+        let access_token = self.access_token_for(folder).await?;
+        let path = dropbox_path(&folder.path);
+        if path.is_empty() {
+            return Ok(());
+        }
+
+        let created: Result<Value, String> =
+            self.rpc(&access_token, "/2/files/create_folder_v2", json!({ "path": path })).await?;
+
+        match created {
+            Ok(_) => Ok(()),
+            Err(summary) if summary.starts_with("path/conflict/folder") => Ok(()),
+            Err(summary) => Err(anyhow::anyhow!("Creating folder {} failed: {}", path, summary)),
+        }
+    }
+
+    /// Small files are sent in one request, larger ones in chunks through an upload session.
+    /// Missing parents are created by Dropbox.
+    async fn upload(
+        &self,
+        file: &DestinationPath,
+        size: u64,
+        mut body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<DriveItem> {
+        let access_token = self.access_token_for(file).await?;
+        let commit = json!({ "path": dropbox_path(&file.path), "mode": "overwrite", "mute": true });
+
+        if size <= UPLOAD_CHUNK_SIZE {
+            let content = read_whole(&mut body, size).await?;
+            let request = self.content_request(&access_token, "/2/files/upload", &commit);
+            let uploaded: Metadata = content_response(request.body(content), "Upload").await?;
+            return Ok(uploaded.into());
+        }
+
+        let mut chunk = read_chunk(&mut body).await?;
+
+        let request = self.content_request(
+            &access_token,
+            "/2/files/upload_session/start",
+            &json!({ "close": false }),
+        );
+        let mut offset = chunk.len() as u64;
+        let session: UploadSessionStart =
+            content_response(request.body(chunk), "Starting upload session").await?;
+
+        loop {
+            chunk = read_chunk(&mut body).await?;
+            if chunk.is_empty() {
+                return Err(anyhow::anyhow!("File ended after {} of {} bytes", offset, size));
+            }
+
+            let cursor = json!({ "session_id": session.session_id, "offset": offset });
+            offset += chunk.len() as u64;
+
+            if offset >= size {
+                let arg = json!({ "cursor": cursor, "commit": commit });
+                let request =
+                    self.content_request(&access_token, "/2/files/upload_session/finish", &arg);
+                let uploaded: Metadata = content_response(request.body(chunk), "Upload").await?;
+                return Ok(uploaded.into());
+            }
+
+            let arg = json!({ "cursor": cursor, "close": false });
+            let request =
+                self.content_request(&access_token, "/2/files/upload_session/append_v2", &arg);
+            content_response::<Value>(request.body(chunk), "Upload chunk").await?;
+        }
+    }
+
+    async fn stat(&self, item: &DestinationPath) -> Result<Option<DriveItem>> {
+        let access_token = self.access_token_for(item).await?;
+        let path = dropbox_path(&item.path);
+        if path.is_empty() {
+            return Err(anyhow::anyhow!("The root of a Dropbox can't be addressed as a file"));
+        }
+
+        let metadata: Result<Metadata, String> =
+            self.rpc(&access_token, "/2/files/get_metadata", json!({ "path": path })).await?;
+
+        match metadata {
+            Ok(metadata) => Ok(Some(metadata.into())),
+            Err(summary) if summary.starts_with("path/not_found") => Ok(None),
+            Err(summary) => Err(anyhow::anyhow!("Getting {} failed: {}", path, summary)),
+        }
+    }
+
+    async fn delete(&self, item: &DestinationPath) -> Result<bool> {
+        let access_token = self.access_token_for(item).await?;
+        let path = dropbox_path(&item.path);
+        if path.is_empty() {
+            return Err(anyhow::anyhow!("The root of a Dropbox can't be deleted"));
+        }
+
+        let deleted: Result<Value, String> =
+            self.rpc(&access_token, "/2/files/delete_v2", json!({ "path": path })).await?;
+
+        match deleted {
+            Ok(_) => Ok(true),
+            Err(summary) if summary.starts_with("path_lookup/not_found") => Ok(false),
+            Err(summary) => Err(anyhow::anyhow!("Deleting {} failed: {}", path, summary)),
+        }
+    }
+}
+
+impl DropboxClient {
+    /// Access token for a destination path, which must be in the user's own Dropbox
+    async fn access_token_for(&self, item: &DestinationPath) -> Result<String> {
+        if item.target != DriveTarget::Default {
+            return Err(anyhow::anyhow!("Dropbox destinations only have the user's own Dropbox"));
+        }
+
+        let user_id = item.user_id.context("Dropbox destinations need a user's connection")?;
+        self.get_access_token(item.owner_id, user_id).await
+    }
+
+    /// Call an RPC endpoint. Errors specific to the endpoint, which Dropbox reports with HTTP
+    /// 409, are returned as their summary so callers can handle the expected ones.
+    async fn rpc<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        endpoint: &str,
+        arg: Value,
+    ) -> Result<Result<T, String>> {
+        let response = self
+            .http_client
+            .post(self.endpoints.api(endpoint))
+            .bearer_auth(access_token)
+            .json(&arg)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send {} request", endpoint))?;
+
+        if response.status() == StatusCode::CONFLICT {
+            let error: ApiError = response.json().await.context("Failed to parse API error")?;
+            return Ok(Err(error.error_summary));
+        }
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+            return Err(anyhow::anyhow!("{} failed: HTTP {}: {}", endpoint, status, text));
+        }
+
+        let result = response.json().await.with_context(|| format!("Failed to parse {}", endpoint));
+        Ok(Ok(result?))
+    }
+
+    /// Start a request to a content endpoint, which takes its arguments in a header
+    fn content_request(&self, access_token: &str, endpoint: &str, arg: &Value) -> RequestBuilder {
+        self.http_client
+            .post(self.endpoints.content(endpoint))
+            .bearer_auth(access_token)
+            .header("Dropbox-API-Arg", api_arg(arg))
+            .header(CONTENT_TYPE, "application/octet-stream")
+    }
+}
+
+/// Send a content request and parse its JSON response, or turn a failed one into an error
+async fn content_response<T: DeserializeOwned>(request: RequestBuilder, action: &str) -> Result<T> {
+    let response = request.send().await.with_context(|| format!("Failed to send {}", action))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+        return Err(anyhow::anyhow!("{} failed: HTTP {}: {}", action, status, text));
+    }
+
+    response.json::<T>().await.with_context(|| format!("Failed to parse {} response", action))
+}
+
+/// Read up to one upload chunk of a file
+async fn read_chunk(body: &mut (dyn AsyncRead + Send + Unpin)) -> Result<Vec<u8>> {
+    let mut chunk = Vec::new();
+    body.take(UPLOAD_CHUNK_SIZE).read_to_end(&mut chunk).await.context("Failed to read file")?;
+    Ok(chunk)
+}
+
+/// A slash-separated path as Dropbox expects it: with a leading slash, or empty for the root
+fn dropbox_path(path: &str) -> String {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", segment))
+        .collect()
+}
+
+/// Serialize the `Dropbox-API-Arg` header, which must be ASCII, escaping everything else as JSON
+/// does for characters outside the Basic Multilingual Plane
+fn api_arg(arg: &Value) -> String {
+    let mut header = String::new();
+    for c in arg.to_string().chars() {
+        if c.is_ascii() {
+            header.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                header.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::{OAuthGrant, Provider};
+    use crate::db::repository::OAuthRepository;
+    use crate::dropbox::tests::test_client;
+    use chrono::Utc;
+    use std::sync::Arc;
+    use wiremock::matchers::{header, header_regex, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_dropbox_path_and_api_arg() {
+        assert_eq!(dropbox_path("Reports//2025/"), "/Reports/2025");
+        assert_eq!(dropbox_path("/"), "");
+        assert_eq!(
+            api_arg(&json!({ "path": "/Café 😀" })),
+            r#"{"path":"/Caf\u00e9 \ud83d\ude00"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_large_upload_uses_session() -> Result<()> {
+        let server = MockServer::start().await;
+        let size = UPLOAD_CHUNK_SIZE * 2 + 10;
+
+        Mock::given(method("POST"))
+            .and(path("/oauth2/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "sl.dropbox-token",
+                "expires_in": 14400
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/2/files/upload_session/start"))
+            .and(header("authorization", "Bearer sl.dropbox-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "session_id": "s1" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/2/files/upload_session/append_v2"))
+            .and(header_regex(
+                "dropbox-api-arg",
+                &format!(r#""offset":{},"session_id":"s1""#, UPLOAD_CHUNK_SIZE),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!(null)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/2/files/upload_session/finish"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "id:big",
+                "name": "big.bin",
+                "size": size
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let grant = OAuthGrant {
+            refresh_token: "refresh-token".to_string(),
+            account_id: None,
+            granted_scopes: None,
+            authorized_at: Utc::now(),
+        };
+        repository.save_oauth_refresh_token(Provider::Dropbox, 123, 456, &grant).await?;
+        let client = test_client(&server, repository);
+
+        let file = DestinationPath {
+            owner_id: 123,
+            user_id: Some(456),
+            target: DriveTarget::Default,
+            path: "/Exports/big.bin".to_string(),
+        };
+        let body = vec![0u8; size as usize];
+        let item = client.upload(&file, size, Box::new(std::io::Cursor::new(body))).await?;
+
+        assert_eq!(item.id, "id:big");
+        assert_eq!(item.size, Some(size));
+
+        // A body that doesn't match its size isn't uploaded truncated
+        let small = DestinationPath { path: "/Exports/small.csv".to_string(), ..file };
+        let body = Box::new(std::io::Cursor::new(b"a,b".to_vec()));
+        let error = client.upload(&small, 5, body).await.unwrap_err();
+        assert_eq!(error.to_string(), "File ended after 3 of 5 bytes");
+
+        Ok(())
+    }
+}
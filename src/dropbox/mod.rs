@@ -0,0 +1,82 @@
+pub mod files;
+
+use anyhow::Result;
+use reqwest::Client;
+use std::sync::Arc;
+
+use crate::db::models::Provider;
+use crate::db::repository::OAuthRepository;
+use crate::oauth::OAuthTokens;
+use crate::onedrive::RefreshLocks;
+
+/// Dropbox API endpoints, configurable for tests
+#[derive(Debug, Clone)]
+pub struct DropboxEndpoints {
+    /// RPC endpoints and OAuth, e.g. `https://api.dropboxapi.com`
+    pub api_url: String,
+    /// Upload endpoints, e.g. `https://content.dropboxapi.com`
+    pub content_url: String,
+}
+
+impl DropboxEndpoints {
+    fn api(&self, path: &str) -> String {
+        format!("{}{}", self.api_url.trim_end_matches('/'), path)
+    }
+
+    fn content(&self, path: &str) -> String {
+        format!("{}{}", self.content_url.trim_end_matches('/'), path)
+    }
+}
+
+/// Client for users' Dropbox accounts, authorized with the refresh tokens they granted
+pub struct DropboxClient {
+    http_client: Client,
+    tokens: OAuthTokens,
+    endpoints: DropboxEndpoints,
+}
+
+impl DropboxClient {
+    pub fn new(
+        repository: Arc<dyn OAuthRepository>,
+        refresh_locks: RefreshLocks,
+        app_key: String,
+        app_secret: String,
+        endpoints: DropboxEndpoints,
+    ) -> Self {
+        let tokens = OAuthTokens::new(
+            Provider::Dropbox,
+            repository,
+            refresh_locks,
+            app_key,
+            app_secret,
+            endpoints.api("/oauth2/token"),
+        );
+
+        Self { http_client: Client::new(), tokens, endpoints }
+    }
+
+    /// Get a valid access token for a user's integration, refreshing if necessary
+    pub async fn get_access_token(&self, owner_id: i64, user_id: i64) -> Result<String> {
+        self.tokens.get_access_token(owner_id, user_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use wiremock::MockServer;
+
+    pub(crate) fn test_client(
+        server: &MockServer,
+        repository: Arc<InMemoryRepository>,
+    ) -> DropboxClient {
+        DropboxClient::new(
+            repository,
+            RefreshLocks::new(),
+            "test-app-key".to_string(),
+            "test-app-secret".to_string(),
+            DropboxEndpoints { api_url: server.uri(), content_url: server.uri() },
+        )
+    }
+}
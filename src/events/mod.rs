@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use aws_sdk_sqs::Client;
+use serde::Serialize;
+
+use crate::messages::Message;
+
+/// Publishes outbound events to an SQS queue consumed by the rest of the product.
+/// Without a configured queue, events are only logged.
+#[derive(Clone)]
+pub struct EventPublisher {
+    client: Client,
+    queue_url: Option<String>,
+}
+
+impl EventPublisher {
+    pub fn new(client: Client, queue_url: Option<String>) -> Self {
+        Self { client, queue_url }
+    }
+
+    pub async fn publish<T: Serialize>(&self, event_type: &str, payload: T) -> Result<()> {
+        let message = Message { event_type: event_type.to_string(), payload };
+        let body = serde_json::to_string(&message).context("Failed to serialize event")?;
+
+        let Some(queue_url) = &self.queue_url else {
+            println!("No events queue configured, dropping event: {}", body);
+            return Ok(());
+        };
+
+        self.client
+            .send_message()
+            .queue_url(queue_url)
+            .message_body(body)
+            .send()
+            .await
+            .context("Failed to publish event")?;
+
+        println!("Published {} event", event_type);
+
+        Ok(())
+    }
+}
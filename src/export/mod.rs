@@ -0,0 +1,72 @@
+pub mod s3;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::AsyncRead;
+
+/// Object storage that files exported from users' drives are written into
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Write an object of `size` bytes, replacing any object at `key`
+    async fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<()>;
+}
+
+/// Key an exported file is written to: its path below the exported folder, under `prefix`
+pub fn export_key(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    let path = path.trim_start_matches('/');
+
+    if prefix.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tokio::io::AsyncReadExt;
+
+    /// Keeps written objects in memory, keyed by bucket and key
+    #[derive(Default)]
+    pub struct MemoryStore {
+        pub objects: Mutex<HashMap<(String, String), Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl ObjectStore for MemoryStore {
+        async fn put(
+            &self,
+            bucket: &str,
+            key: &str,
+            _size: u64,
+            mut body: Box<dyn AsyncRead + Send + Unpin>,
+        ) -> Result<()> {
+            let mut content = Vec::new();
+            body.read_to_end(&mut content).await?;
+            self.objects.lock().unwrap().insert((bucket.to_string(), key.to_string()), content);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_key() {
+        assert_eq!(export_key("", "report.pdf"), "report.pdf");
+        assert_eq!(export_key("exports/2025/", "Reports/q1.pdf"), "exports/2025/Reports/q1.pdf");
+        assert_eq!(export_key("/exports", "/q1.pdf"), "exports/q1.pdf");
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::ObjectStore;
+use crate::destination::read_whole;
+
+// Objects up to this size are written in one request, larger ones in parts of it through a
+// multipart upload. S3 requires parts other than the last to be at least 5 MiB.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Writes objects to S3 or an S3-compatible store
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+}
+
+impl S3Store {
+    pub fn new(client: aws_sdk_s3::Client) -> Self {
+        Self { client }
+    }
+
+    /// Upload the parts of a multipart upload, returning them for completing it
+    async fn upload_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<Vec<CompletedPart>> {
+        let mut parts = Vec::new();
+        loop {
+            let part = read_part(body).await?;
+            if part.is_empty() {
+                return Ok(parts);
+            }
+
+            let part_number = parts.len() as i32 + 1;
+            let uploaded = self
+                .client
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .with_context(|| format!("Failed to upload part {} to S3", part_number))?;
+
+            parts.push(
+                CompletedPart::builder().part_number(part_number).set_e_tag(uploaded.e_tag).build(),
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    /// Large objects are written through a multipart upload, which is aborted if it fails so
+    /// its parts aren't kept around.
+    async fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        mut body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<()> {
+        if size <= PART_SIZE {
+            let content = read_whole(&mut body, size).await?;
+            self.client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(content))
+                .send()
+                .await
+                .context("Failed to upload file to S3")?;
+            return Ok(());
+        }
+
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .context("Failed to start multipart upload to S3")?;
+        let upload_id = upload.upload_id.context("S3 did not return an upload ID")?;
+
+        let completed = match self.upload_parts(bucket, key, &upload_id, &mut body).await {
+            Ok(parts) => self
+                .client
+                .complete_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder().set_parts(Some(parts)).build(),
+                )
+                .send()
+                .await
+                .context("Failed to complete multipart upload to S3")
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        if completed.is_err() {
+            let aborted = self
+                .client
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+            if let Err(e) = aborted {
+                println!("Warning: Failed to abort multipart upload {}: {}", upload_id, e);
+            }
+        }
+
+        completed
+    }
+}
+
+/// Read up to one part of a file
+async fn read_part(body: &mut (dyn AsyncRead + Send + Unpin)) -> Result<Vec<u8>> {
+    let mut part = Vec::new();
+    body.take(PART_SIZE).read_to_end(&mut part).await.context("Failed to read file")?;
+    Ok(part)
+}
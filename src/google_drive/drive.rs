@@ -0,0 +1,430 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::header::{CONTENT_RANGE, LOCATION};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::GoogleDriveClient;
+use crate::destination::{Destination, DestinationPath};
+use crate::oauth::{check_response, failed_response, json_response};
+use crate::onedrive::drive::{DriveItem, DriveTarget};
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+// Resumable upload chunks must be a multiple of 256 KiB
+const UPLOAD_CHUNK_SIZE: u64 = 32 * 256 * 1024;
+
+// Returned with every file so it can be turned into a `DriveItem`
+const FILE_FIELDS: &str = "id,name,mimeType,size,webViewLink";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleFile {
+    id: String,
+    name: String,
+    mime_type: String,
+    /// Only set for files with content, as a decimal string
+    size: Option<String>,
+    web_view_link: Option<String>,
+}
+
+impl From<GoogleFile> for DriveItem {
+    fn from(file: GoogleFile) -> Self {
+        DriveItem {
+            size: file.size.and_then(|size| size.parse().ok()),
+            id: file.id,
+            name: file.name,
+            web_url: file.web_view_link,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FileList {
+    files: Vec<GoogleFile>,
+}
+
+/// The drive a destination path is in: the user's My Drive, or a shared drive
+struct DriveScope {
+    access_token: String,
+    shared_drive_id: Option<String>,
+}
+
+impl DriveScope {
+    /// ID of the folder that paths are relative to. A shared drive's ID is also its root's.
+    fn root_id(&self) -> &str {
+        self.shared_drive_id.as_deref().unwrap_or("root")
+    }
+}
+
+#[async_trait]
+impl Destination for GoogleDriveClient {
+    async fn ensure_folder(&self, folder: &DestinationPath) -> Result<()> {
+        let scope = self.scope(folder).await?;
+        self.resolve_folder(&scope, &segments(&folder.path), true).await?;
+        Ok(())
+    }
+
+    /// Sent through a resumable upload session in chunks. An existing file at the path gets a new
+    /// revision rather than a duplicate, since Drive allows several files with the same name in
+    /// a folder.
+    async fn upload(
+        &self,
+        file: &DestinationPath,
+        size: u64,
+        mut body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<DriveItem> {
+        let scope = self.scope(file).await?;
+        let path = segments(&file.path);
+        let Some((name, parents)) = path.split_last() else {
+            return Err(anyhow::anyhow!("Google Drive path {} has no file name", file.path));
+        };
+
+        let parent_id = self
+            .resolve_folder(&scope, parents, true)
+            .await?
+            .context("Failed to create parent folders")?;
+        let existing = self.find_child(&scope, &parent_id, name, false).await?;
+
+        let request = match existing {
+            Some(existing) => self
+                .http_client
+                .patch(self.endpoints.api(&format!("/upload/drive/v3/files/{}", existing.id)))
+                .json(&serde_json::json!({})),
+            None => self
+                .http_client
+                .post(self.endpoints.api("/upload/drive/v3/files"))
+                .json(&serde_json::json!({ "name": name, "parents": [parent_id] })),
+        };
+        let response = request
+            .query(&[
+                ("uploadType", "resumable"),
+                ("supportsAllDrives", "true"),
+                ("fields", FILE_FIELDS),
+            ])
+            .bearer_auth(&scope.access_token)
+            .header("X-Upload-Content-Length", size)
+            .send()
+            .await
+            .context("Failed to send start upload request")?;
+
+        let response = check_response(response, "Starting upload").await?;
+        let session_url = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .context("Upload session has no location")?
+            .to_string();
+
+        let mut offset = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(UPLOAD_CHUNK_SIZE.min(size) as usize);
+            (&mut body)
+                .take(UPLOAD_CHUNK_SIZE)
+                .read_to_end(&mut chunk)
+                .await
+                .context("Failed to read file")?;
+
+            if chunk.is_empty() && offset < size {
+                return Err(anyhow::anyhow!("File ended after {} of {} bytes", offset, size));
+            }
+
+            let end = offset + chunk.len() as u64;
+            // Empty files are uploaded with a single empty chunk
+            let range = if chunk.is_empty() {
+                format!("bytes */{}", size)
+            } else {
+                format!("bytes {}-{}/{}", offset, end - 1, size)
+            };
+
+            let response = self
+                .http_client
+                .put(&session_url)
+                .bearer_auth(&scope.access_token)
+                .header(CONTENT_RANGE, range)
+                .body(chunk)
+                .send()
+                .await
+                .context("Failed to send upload chunk")?;
+
+            // Google answers the final chunk with the file, and every other one with 308
+            if end >= size {
+                let uploaded: GoogleFile = json_response(response, "Upload").await?;
+                return Ok(uploaded.into());
+            }
+
+            if response.status() != StatusCode::PERMANENT_REDIRECT {
+                return Err(failed_response(response, "Upload chunk").await);
+            }
+
+            offset = end;
+        }
+    }
+
+    async fn stat(&self, item: &DestinationPath) -> Result<Option<DriveItem>> {
+        let scope = self.scope(item).await?;
+
+        Ok(self.find(&scope, &item.path).await?.map(DriveItem::from))
+    }
+
+    async fn delete(&self, item: &DestinationPath) -> Result<bool> {
+        let scope = self.scope(item).await?;
+        let Some(file) = self.find(&scope, &item.path).await? else {
+            return Ok(false);
+        };
+
+        let response = self
+            .http_client
+            .delete(self.endpoints.api(&format!("/drive/v3/files/{}", file.id)))
+            .query(&[("supportsAllDrives", "true")])
+            .bearer_auth(&scope.access_token)
+            .send()
+            .await
+            .context("Failed to send delete request")?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            _ => Err(failed_response(response, "Deleting file").await),
+        }
+    }
+}
+
+impl GoogleDriveClient {
+    /// Access token and drive of a destination path. `drive_id` names a shared drive; the other
+    /// Microsoft 365 targets have no Google Drive equivalent.
+    async fn scope(&self, item: &DestinationPath) -> Result<DriveScope> {
+        let shared_drive_id = match &item.target {
+            DriveTarget::Default => None,
+            DriveTarget::Drive(drive_id) => Some(drive_id.clone()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Google Drive destinations only support My Drive or a shared drive_id"
+                ))
+            }
+        };
+
+        let user_id = item.user_id.context("Google Drive destinations need a user's connection")?;
+        let access_token = self.get_access_token(item.owner_id, user_id).await?;
+
+        Ok(DriveScope { access_token, shared_drive_id })
+    }
+
+    /// Find the file or folder at a path
+    async fn find(&self, scope: &DriveScope, path: &str) -> Result<Option<GoogleFile>> {
+        let path = segments(path);
+        let Some((name, parents)) = path.split_last() else {
+            return Err(anyhow::anyhow!("The root of a drive can't be addressed as a file"));
+        };
+
+        match self.resolve_folder(scope, parents, false).await? {
+            Some(parent_id) => self.find_child(scope, &parent_id, name, false).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Walk a folder path down from the root, creating missing folders if `create` is set.
+    /// Returns `None` if a folder is missing and isn't created.
+    async fn resolve_folder(
+        &self,
+        scope: &DriveScope,
+        path: &[&str],
+        create: bool,
+    ) -> Result<Option<String>> {
+        let mut folder_id = scope.root_id().to_string();
+
+        for name in path {
+            folder_id = match self.find_child(scope, &folder_id, name, true).await? {
+                Some(folder) => folder.id,
+                None if create => self.create_folder(scope, &folder_id, name).await?.id,
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some(folder_id))
+    }
+
+    /// Find a file or folder by name in a folder, ignoring anything in the trash
+    async fn find_child(
+        &self,
+        scope: &DriveScope,
+        parent_id: &str,
+        name: &str,
+        folder: bool,
+    ) -> Result<Option<GoogleFile>> {
+        let mut query = format!(
+            "'{}' in parents and name = '{}' and trashed = false",
+            escape_query(parent_id),
+            escape_query(name)
+        );
+        if folder {
+            query.push_str(&format!(" and mimeType = '{}'", FOLDER_MIME_TYPE));
+        }
+        let fields = format!("files({})", FILE_FIELDS);
+
+        let mut params = vec![
+            ("q", query.as_str()),
+            ("fields", fields.as_str()),
+            ("supportsAllDrives", "true"),
+            ("includeItemsFromAllDrives", "true"),
+        ];
+        if let Some(drive_id) = &scope.shared_drive_id {
+            params.extend([("corpora", "drive"), ("driveId", drive_id.as_str())]);
+        }
+
+        let response = self
+            .http_client
+            .get(self.endpoints.api("/drive/v3/files"))
+            .query(&params)
+            .bearer_auth(&scope.access_token)
+            .send()
+            .await
+            .context("Failed to send file search request")?;
+        let list: FileList = json_response(response, "Searching files").await?;
+
+        // Prefer a folder when both a folder and a file have the name
+        let mut files = list.files;
+        files.sort_by_key(|file| file.mime_type != FOLDER_MIME_TYPE);
+        Ok(files.into_iter().next())
+    }
+
+    async fn create_folder(
+        &self,
+        scope: &DriveScope,
+        parent_id: &str,
+        name: &str,
+    ) -> Result<GoogleFile> {
+        let response = self
+            .http_client
+            .post(self.endpoints.api("/drive/v3/files"))
+            .query(&[("supportsAllDrives", "true"), ("fields", FILE_FIELDS)])
+            .bearer_auth(&scope.access_token)
+            .json(&serde_json::json!({
+                "name": name,
+                "mimeType": FOLDER_MIME_TYPE,
+                "parents": [parent_id]
+            }))
+            .send()
+            .await
+            .context("Failed to send create folder request")?;
+
+        json_response(response, "Creating folder").await
+    }
+}
+
+/// The non-empty segments of a slash-separated path
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|segment| !segment.is_empty()).collect()
+}
+
+/// Escape a string literal in a Drive search query
+fn escape_query(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::{OAuthGrant, Provider};
+    use crate::db::repository::OAuthRepository;
+    use crate::google_drive::tests::test_client;
+    use chrono::Utc;
+    use std::sync::Arc;
+    use wiremock::matchers::{body_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_escape_query() {
+        assert_eq!(escape_query(r"Bob's \ reports"), r"Bob\'s \\ reports");
+    }
+
+    #[tokio::test]
+    async fn test_upload_creates_folders_and_file() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "google-token",
+                "expires_in": 3599
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // Neither the folder nor the file exist yet
+        Mock::given(method("GET"))
+            .and(path("/drive/v3/files"))
+            .and(header("authorization", "Bearer google-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "files": []
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/drive/v3/files"))
+            .and(body_json(serde_json::json!({
+                "name": "Reports",
+                "mimeType": FOLDER_MIME_TYPE,
+                "parents": ["root"]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "folder-1",
+                "name": "Reports",
+                "mimeType": FOLDER_MIME_TYPE
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/upload/drive/v3/files"))
+            .and(query_param("uploadType", "resumable"))
+            .and(header("x-upload-content-length", "5"))
+            .and(body_json(serde_json::json!({ "name": "q1.csv", "parents": ["folder-1"] })))
+            .respond_with(ResponseTemplate::new(200).insert_header(
+                "location",
+                format!("{}/upload-session?upload_id=abc", server.uri()).as_str(),
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/upload-session"))
+            .and(header("content-range", "bytes 0-4/5"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "file-1",
+                "name": "q1.csv",
+                "mimeType": "text/csv",
+                "size": "5",
+                "webViewLink": "https://drive.google.com/file/d/file-1/view"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let grant = OAuthGrant {
+            refresh_token: "refresh-token".to_string(),
+            account_id: None,
+            granted_scopes: None,
+            authorized_at: Utc::now(),
+        };
+        repository.save_oauth_refresh_token(Provider::GoogleDrive, 123, 456, &grant).await?;
+        let client = test_client(&server, repository);
+
+        let file = DestinationPath {
+            owner_id: 123,
+            user_id: Some(456),
+            target: DriveTarget::Default,
+            path: "/Reports/q1.csv".to_string(),
+        };
+        let item = client.upload(&file, 5, Box::new(&b"a,b,c"[..])).await?;
+
+        assert_eq!(item.id, "file-1");
+        assert_eq!(item.size, Some(5));
+
+        Ok(())
+    }
+}
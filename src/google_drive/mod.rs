@@ -0,0 +1,77 @@
+pub mod drive;
+
+use anyhow::Result;
+use reqwest::Client;
+use std::sync::Arc;
+
+use crate::db::models::Provider;
+use crate::db::repository::OAuthRepository;
+use crate::oauth::OAuthTokens;
+use crate::onedrive::RefreshLocks;
+
+/// Google OAuth and Drive API endpoints, configurable for tests
+#[derive(Debug, Clone)]
+pub struct GoogleEndpoints {
+    pub token_url: String,
+    /// Base of both the Drive API and its upload endpoint, e.g. `https://www.googleapis.com`
+    pub api_url: String,
+}
+
+impl GoogleEndpoints {
+    fn api(&self, path: &str) -> String {
+        format!("{}{}", self.api_url.trim_end_matches('/'), path)
+    }
+}
+
+/// Client for users' Google Drives, authorized with the refresh tokens they granted
+pub struct GoogleDriveClient {
+    http_client: Client,
+    tokens: OAuthTokens,
+    endpoints: GoogleEndpoints,
+}
+
+impl GoogleDriveClient {
+    pub fn new(
+        repository: Arc<dyn OAuthRepository>,
+        refresh_locks: RefreshLocks,
+        client_id: String,
+        client_secret: String,
+        endpoints: GoogleEndpoints,
+    ) -> Self {
+        let tokens = OAuthTokens::new(
+            Provider::GoogleDrive,
+            repository,
+            refresh_locks,
+            client_id,
+            client_secret,
+            endpoints.token_url.clone(),
+        );
+
+        Self { http_client: Client::new(), tokens, endpoints }
+    }
+
+    /// Get a valid access token for a user's integration, refreshing if necessary
+    pub async fn get_access_token(&self, owner_id: i64, user_id: i64) -> Result<String> {
+        self.tokens.get_access_token(owner_id, user_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use wiremock::MockServer;
+
+    pub(crate) fn test_client(
+        server: &MockServer,
+        repository: Arc<InMemoryRepository>,
+    ) -> GoogleDriveClient {
+        GoogleDriveClient::new(
+            repository,
+            RefreshLocks::new(),
+            "test-client-id".to_string(),
+            "test-client-secret".to_string(),
+            GoogleEndpoints { token_url: format!("{}/token", server.uri()), api_url: server.uri() },
+        )
+    }
+}
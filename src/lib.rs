@@ -0,0 +1,20 @@
+//! Syncs files from S3, Azure Blob Storage, shared volumes or URLs into users' OneDrive, Google
+//! Drive, Dropbox, WebDAV or SFTP servers, or into a local directory, driven by messages on an SQS queue.
+//!
+//! The worker binary consumes the queue with [`worker::process_message`]. Producers can build
+//! the messages it consumes with the types in [`messages`].
+
+pub mod config;
+pub mod db;
+pub mod destination;
+pub mod dropbox;
+pub mod events;
+pub mod export;
+pub mod google_drive;
+pub mod messages;
+pub mod oauth;
+pub mod onedrive;
+pub mod sftp;
+pub mod source;
+pub mod webdav;
+pub mod worker;
@@ -1,17 +1,22 @@
-mod config;
-mod db;
-mod messages;
-mod onedrive;
-
-use anyhow::{Context, Result};
+use anyhow::Result;
 use aws_config::BehaviorVersion;
-use aws_sdk_sqs::{Client, Error as SqsError};
-use std::{cmp::min, time::Duration};
-
-use crate::messages::{parse_message, MessageType};
+use aws_sdk_sqs::Client;
+use std::{sync::Arc, time::Duration};
+
+use ferris_file_sync::db::repository::PgRepository;
+use ferris_file_sync::destination::{local::LocalDestination, Destination, Destinations};
+use ferris_file_sync::export::s3::S3Store;
+use ferris_file_sync::sftp::SftpClient;
+use ferris_file_sync::source::{http::HttpSource, local::LocalSource, s3::S3Source, Sources};
+use ferris_file_sync::webdav::WebDavClient;
+use ferris_file_sync::worker::{
+    azure_blob_source, dropbox_client, google_drive_client, onedrive_credential,
+    onedrive_endpoints, process_message, Services,
+};
+use ferris_file_sync::{config, db, events, onedrive};
 
 #[tokio::main]
-async fn main() -> Result<(), SqsError> {
+async fn main() -> Result<()> {
     dotenv::dotenv().ok();
 
     let config = config::Config::from_env().expect("Failed to load config");
@@ -20,6 +25,8 @@ async fn main() -> Result<(), SqsError> {
 
     db::run_migrations(&pool).await.expect("Failed to run migrations");
 
+    let repository = Arc::new(PgRepository::new(pool, config.encryption_key.clone()));
+
     let mut aws_config_builder = aws_config::defaults(BehaviorVersion::latest())
         .region(aws_types::region::Region::new(config.aws_region.clone()));
 
@@ -29,6 +36,82 @@ async fn main() -> Result<(), SqsError> {
 
     let aws_config = aws_config_builder.load().await;
     let client = Client::new(&aws_config);
+    // LocalStack only serves path-style bucket URLs
+    let s3_client = aws_sdk_s3::Client::from_conf(
+        aws_sdk_s3::config::Builder::from(&aws_config)
+            .force_path_style(config.s3_endpoint.is_some())
+            .build(),
+    );
+
+    let sources = Sources {
+        s3: S3Source::new(s3_client.clone()),
+        local: config.local_source_root.as_ref().map(LocalSource::new),
+        http: HttpSource::new(config.allow_insecure_urls),
+        azure: azure_blob_source(&config),
+    };
+
+    let exports = S3Store::new(s3_client);
+
+    let events = events::EventPublisher::new(client.clone(), config.events_queue_url.clone());
+    let onedrive_credential =
+        onedrive_credential(&config).expect("Failed to load OneDrive client credential");
+
+    // Shared by every message and the token refresher, so concurrent refreshes for the same
+    // integration are serialized
+    let onedrive_client = Arc::new(onedrive::OneDriveClient::new(
+        repository.clone(),
+        onedrive::RefreshLocks::new(),
+        events,
+        config.onedrive_client_id.clone(),
+        onedrive_credential,
+        onedrive_endpoints(&config),
+    ));
+
+    let google_drive = google_drive_client(&config, repository.clone())
+        .map(|client| Arc::new(client) as Arc<dyn Destination>);
+    let dropbox = dropbox_client(&config, repository.clone())
+        .map(|client| Arc::new(client) as Arc<dyn Destination>);
+
+    let destinations = Destinations {
+        onedrive: onedrive_client.clone(),
+        local: config.local_destination_root.as_ref().map(LocalDestination::new),
+        google_drive,
+        dropbox,
+        webdav: Arc::new(WebDavClient::new(repository.clone())),
+        sftp: Arc::new(SftpClient::new(repository.clone(), &config.sftp_program)),
+    };
+
+    let services = Services {
+        integrations: repository.as_ref(),
+        jobs: repository.as_ref(),
+        oauth: repository.as_ref(),
+        webdav: repository.as_ref(),
+        sftp: repository.as_ref(),
+        watches: repository.as_ref(),
+        tokens: onedrive_client.as_ref(),
+        drive: onedrive_client.as_ref(),
+        source: &sources,
+        destinations: &destinations,
+        exports: &exports,
+    };
+
+    let token_refresher = onedrive::refresher::TokenRefresher::new(
+        onedrive_client.clone(),
+        repository.clone(),
+        Duration::from_secs(config.token_refresh_interval_secs),
+        chrono::Duration::seconds(config.token_refresh_lead_secs),
+        chrono::Duration::days(config.refresh_token_keepalive_days),
+    );
+    tokio::spawn(token_refresher.run());
+
+    // Exports of changed files in watched folders are queued like any other message
+    let folder_watcher = onedrive::watcher::FolderWatcher::new(
+        onedrive_client.clone(),
+        repository.clone(),
+        events::EventPublisher::new(client.clone(), Some(config.queue_url.clone())),
+        Duration::from_secs(config.watch_poll_interval_secs),
+    );
+    tokio::spawn(folder_watcher.run());
 
     println!("Ferris File Sync SQS Consumer starting...");
     println!("Listening for messages on queue: {}", config.queue_url);
@@ -47,7 +130,7 @@ async fn main() -> Result<(), SqsError> {
                 println!("Processing message ID: {}", message.message_id().unwrap_or("unknown"));
 
                 if let Some(body) = &message.body {
-                    match process_message(body, &pool, &config).await {
+                    match process_message(body, &config, &services).await {
                         Ok(_) => println!("Message processed successfully"),
                         Err(e) => println!("Error processing message: {}", e),
                     }
@@ -71,73 +154,3 @@ async fn main() -> Result<(), SqsError> {
         }
     }
 }
-
-async fn process_message(
-    message_body: &str,
-    pool: &sqlx::PgPool,
-    config: &config::Config,
-) -> Result<(), anyhow::Error> {
-    let message = parse_message(message_body).context("Failed to parse message")?;
-
-    let onedrive_client = onedrive::OneDriveClient::new(
-        pool.clone(),
-        config.encryption_key.clone(),
-        config.onedrive_client_id.clone(),
-        config.onedrive_client_secret.clone(),
-    );
-
-    match message {
-        MessageType::OneDriveAuthorization { payload } => {
-            println!(
-                "Handling OneDrive authorization for owner: {}, user: {}",
-                payload.owner_id, payload.user_id
-            );
-
-            db::onedrive::save_refresh_token(
-                pool,
-                payload.owner_id,
-                payload.user_id,
-                &payload.refresh_token,
-                &config.encryption_key,
-            )
-            .await
-            .context("Failed to save OneDrive refresh token")?;
-
-            println!("OneDrive refresh token saved for owner: {}", payload.owner_id);
-
-            match onedrive_client.get_access_token(payload.owner_id).await {
-                Ok(access_token) => {
-                    println!("Successfully validated refresh token and obtained access token");
-                    println!("Access token: {}...", &access_token[0..min(20, access_token.len())]);
-                    println!("OneDrive integration is now ready for use");
-                }
-                Err(e) => {
-                    println!("Warning: Saved refresh token, but token validation failed: {}", e);
-                    println!("The refresh token may be invalid or expired");
-                }
-            }
-        }
-
-        MessageType::FileSync { payload } => {
-            println!("Handling file sync request for owner: {}", payload.owner_id);
-            println!("  - Source: s3://{}/{}", payload.bucket, payload.key);
-            println!("  - Destination: {}", payload.destination);
-
-            match onedrive_client.get_access_token(payload.owner_id).await {
-                Ok(access_token) => {
-                    println!("Successfully obtained access token: {}...", &access_token[0..20]);
-                    // TODO: Implement file sync logic once token refresh is working
-                    // 1. Download the file from S3
-                    // 2. Upload the file to OneDrive
-                    // 3. Update the file status in the database
-                }
-                Err(e) => {
-                    println!("Error getting access token: {}", e);
-                    return Err(anyhow::anyhow!("Failed to get OneDrive access token: {}", e));
-                }
-            }
-        }
-    }
-
-    Ok(())
-}
@@ -1,5 +1,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::db::models::{MicrosoftCloud, Provider};
 
 /// Base message structure that all message types use
 #[derive(Debug, Serialize, Deserialize)]
@@ -14,20 +17,256 @@ pub struct OneDriveAuthorizationPayload {
     pub refresh_token: String,
     pub owner_id: i64,
     pub user_id: i64,
+    /// Tenant the refresh token was issued by, if not the deployment's default
+    pub tenant_id: Option<String>,
+    /// Space-separated scopes the user was asked to consent to, if not full drive access
+    pub scope: Option<String>,
+    /// National cloud the tenant is in, e.g. `us_gov`, if not the deployment's endpoints
+    pub cloud: Option<MicrosoftCloud>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// OneDrive authorization code event, sent instead of `OneDriveAuthorizationPayload` so the
+/// code is exchanged here and refresh tokens never transit the queue
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneDriveAuthorizationCodePayload {
+    pub code: String,
+    pub redirect_uri: String,
+    pub code_verifier: String,
+    pub owner_id: i64,
+    pub user_id: i64,
+    /// Tenant the code was issued by, if not the deployment's default
+    pub tenant_id: Option<String>,
+    /// Scopes passed to the authorize endpoint, if not full drive access
+    pub scope: Option<String>,
+    /// National cloud the tenant is in, e.g. `us_gov`, if not the deployment's endpoints
+    pub cloud: Option<MicrosoftCloud>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// OneDrive tenant authorization event, sent once an organization's admin granted tenant-wide
+/// consent. Files are written with app-only tokens into a user's OneDrive or a site's library.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneDriveTenantAuthorizationPayload {
+    pub tenant_id: String,
+    pub owner_id: i64,
+    pub user_id: i64,
+    /// ID or user principal name of the user whose OneDrive files are written into
+    pub drive_user: Option<String>,
+    /// SharePoint site whose default document library files are written into
+    pub site_id: Option<String>,
+    /// National cloud the tenant is in, e.g. `us_gov`, if not the deployment's endpoints
+    pub cloud: Option<MicrosoftCloud>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Where a synced file is read from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileSource {
+    S3 {
+        bucket: String,
+        key: String,
+    },
+    /// A file under the worker's `LOCAL_SOURCE_ROOT`, e.g. on a shared volume
+    Local {
+        path: String,
+    },
+    /// An HTTP(S) URL, e.g. a pre-signed one
+    Url {
+        url: String,
+    },
+    /// A blob in the worker's Azure Storage account, read with `sas_token` when given instead
+    /// of the configured credential
+    AzureBlob {
+        container: String,
+        blob: String,
+        sas_token: Option<String>,
+    },
+}
+
+impl FileSource {
+    /// Name of the file, the last segment of its key, path or URL path
+    pub fn file_name(&self) -> Option<&str> {
+        let path = match self {
+            FileSource::S3 { key, .. } => key.as_str(),
+            FileSource::AzureBlob { blob, .. } => blob.as_str(),
+            FileSource::Local { path } => path.as_str(),
+            FileSource::Url { url } => {
+                let url = url.split(['?', '#']).next().unwrap_or(url);
+                let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+                without_scheme.split_once('/').map_or("", |(_, path)| path)
+            }
+        };
+
+        path.rsplit('/').next().filter(|name| !name.is_empty())
+    }
+}
+
+/// Shown in logs and recorded on sync jobs. Signatures, such as the query string of pre-signed
+/// URLs and SAS tokens, are left out.
+impl fmt::Display for FileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileSource::S3 { bucket, key } => write!(f, "s3://{}/{}", bucket, key),
+            FileSource::Local { path } => write!(f, "file://{}", path),
+            FileSource::Url { url } => write!(f, "{}", url.split(['?', '#']).next().unwrap_or(url)),
+            FileSource::AzureBlob { container, blob, .. } => {
+                write!(f, "azure://{}/{}", container, blob)
+            }
+        }
+    }
+}
+
+/// Google Drive authorization event, sent once a user granted access to their Google Drive
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleDriveAuthorizationPayload {
+    pub refresh_token: String,
+    pub owner_id: i64,
+    pub user_id: i64,
+    /// Space-separated scopes the user consented to
+    pub scope: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Dropbox authorization event, sent once a user linked their Dropbox account
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DropboxAuthorizationPayload {
+    pub refresh_token: String,
+    pub owner_id: i64,
+    pub user_id: i64,
+    /// Dropbox account the user linked
+    pub account_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// WebDAV connection event, sent once a user entered the credentials of their WebDAV server,
+/// such as a Nextcloud app password
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebDavConnectionPayload {
+    /// Collection synced files are written under, e.g.
+    /// `https://cloud.example.com/remote.php/dav/files/alice`
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// SFTP connection event, sent once a user entered the details of their SFTP server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SftpConnectionPayload {
+    pub host: String,
+    /// 22 unless given
+    pub port: Option<u16>,
+    pub username: String,
+    /// OpenSSH private key the user authorized on the server
+    pub private_key: String,
+    /// Public host key the server must present, e.g. `ssh-ed25519 AAAAC3...`
+    pub host_key: String,
+    /// Directory synced files are written under, the login directory unless given
+    pub root: Option<String>,
+    pub owner_id: i64,
+    pub user_id: i64,
     pub timestamp: DateTime<Utc>,
 }
 
 /// File sync request event
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileSyncPayload {
-    pub bucket: String,
-    pub key: String,
+    /// Where the file is read from. Messages without it name an S3 object with `bucket` and `key`.
+    pub source: Option<FileSource>,
+    pub bucket: Option<String>,
+    pub key: Option<String>,
     pub destination: String,
+    /// Where the file is written, OneDrive unless given
+    #[serde(default)]
+    pub provider: Provider,
+    pub owner_id: i64,
+    pub user_id: Option<i64>,
+    /// Drive to write into instead of the connection's own, e.g. a SharePoint document library
+    pub drive_id: Option<String>,
+    /// SharePoint site whose default document library is written into
+    pub site_id: Option<String>,
+    /// Microsoft 365 group, or team, whose document library is written into
+    pub group_id: Option<String>,
+    /// Channel of the `group_id` team whose files folder is written into
+    pub channel_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl FileSyncPayload {
+    /// Where the file is read from
+    pub fn source(&self) -> Option<FileSource> {
+        match (&self.source, &self.bucket, &self.key) {
+            (Some(source), _, _) => Some(source.clone()),
+            (None, Some(bucket), Some(key)) => {
+                Some(FileSource::S3 { bucket: bucket.clone(), key: key.clone() })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// OneDrive export request event: a file or folder, addressed by exactly one of `item_path` or
+/// `item_id`, is copied from the user's OneDrive into S3
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneDriveExportPayload {
+    pub owner_id: i64,
+    /// The owner's default user unless given
+    pub user_id: Option<i64>,
+    /// Path relative to the drive's root
+    pub item_path: Option<String>,
+    pub item_id: Option<String>,
+    /// Drive to read from instead of the connection's own, e.g. a SharePoint document library
+    pub drive_id: Option<String>,
+    pub bucket: String,
+    /// Key prefix the files are written under, keeping their paths below an exported folder
+    #[serde(default)]
+    pub prefix: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// OneDrive watch event: new and modified files in a folder of the user's OneDrive are exported
+/// into S3 from now on. Registering again replaces the user's watched folder.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneDriveWatchPayload {
     pub owner_id: i64,
+    /// The owner's default user unless given
     pub user_id: Option<i64>,
+    /// Path relative to the drive's root
+    pub folder_path: String,
+    /// Drive to watch instead of the connection's own, e.g. a SharePoint document library
+    pub drive_id: Option<String>,
+    pub bucket: String,
+    /// Key prefix the files are written under, keeping their paths below the folder
+    #[serde(default)]
+    pub prefix: String,
     pub timestamp: DateTime<Utc>,
 }
 
+/// OneDrive disconnect event, sent when the user disconnects OneDrive in the product
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneDriveDisconnectPayload {
+    pub owner_id: i64,
+    pub user_id: i64,
+    /// Integrations authorized after this are left connected
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Published when an owner's OneDrive integration was deactivated because Microsoft rejected its
+/// refresh token, so the product can prompt the user to connect OneDrive again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneDriveReauthorizationRequiredPayload {
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub reason: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub const ONEDRIVE_REAUTHORIZATION_REQUIRED: &str = "onedrive_reauthorization_required";
+
 /// Message type enumeration for easy pattern matching
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "event_type")]
@@ -35,8 +274,35 @@ pub enum MessageType {
     #[serde(rename = "onedrive_authorization")]
     OneDriveAuthorization { payload: OneDriveAuthorizationPayload },
 
+    #[serde(rename = "onedrive_authorization_code")]
+    OneDriveAuthorizationCode { payload: OneDriveAuthorizationCodePayload },
+
+    #[serde(rename = "onedrive_tenant_authorization")]
+    OneDriveTenantAuthorization { payload: OneDriveTenantAuthorizationPayload },
+
     #[serde(rename = "file_sync")]
     FileSync { payload: FileSyncPayload },
+
+    #[serde(rename = "onedrive_export")]
+    OneDriveExport { payload: OneDriveExportPayload },
+
+    #[serde(rename = "onedrive_watch")]
+    OneDriveWatch { payload: OneDriveWatchPayload },
+
+    #[serde(rename = "onedrive_disconnect")]
+    OneDriveDisconnect { payload: OneDriveDisconnectPayload },
+
+    #[serde(rename = "google_drive_authorization")]
+    GoogleDriveAuthorization { payload: GoogleDriveAuthorizationPayload },
+
+    #[serde(rename = "dropbox_authorization")]
+    DropboxAuthorization { payload: DropboxAuthorizationPayload },
+
+    #[serde(rename = "webdav_connection")]
+    WebDavConnection { payload: WebDavConnectionPayload },
+
+    #[serde(rename = "sftp_connection")]
+    SftpConnection { payload: SftpConnectionPayload },
 }
 
 /// Parse a raw message string into a typed message
@@ -44,6 +310,11 @@ pub fn parse_message(message_str: &str) -> Result<MessageType, serde_json::Error
     serde_json::from_str(message_str)
 }
 
+/// Serialize a typed message into the raw string `parse_message` accepts, for producers
+pub fn serialize_message(message: &MessageType) -> Result<String, serde_json::Error> {
+    serde_json::to_string(message)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,11 +338,68 @@ mod tests {
             MessageType::OneDriveAuthorization { payload } => {
                 assert_eq!(payload.user_id, 456);
                 assert!(payload.refresh_token.starts_with("M.R3_BAY"));
+                assert!(payload.tenant_id.is_none());
+                assert!(payload.cloud.is_none());
             }
             _ => panic!("Expected OneDriveAuthorization message"),
         }
     }
 
+    #[test]
+    fn test_parse_onedrive_auth_code_message() {
+        let message_str = r#"
+        {
+            "event_type": "onedrive_authorization_code",
+            "payload": {
+                "code": "M.C507_BAY...",
+                "redirect_uri": "https://app.example.com/onedrive/callback",
+                "code_verifier": "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk",
+                "owner_id": 123,
+                "user_id": 456,
+                "timestamp": "2025-05-03T10:00:00Z"
+            }
+        }
+        "#;
+
+        let message = parse_message(message_str).unwrap();
+        match message {
+            MessageType::OneDriveAuthorizationCode { payload } => {
+                assert_eq!(payload.user_id, 456);
+                assert!(payload.code.starts_with("M.C507_BAY"));
+                assert_eq!(payload.redirect_uri, "https://app.example.com/onedrive/callback");
+            }
+            _ => panic!("Expected OneDriveAuthorizationCode message"),
+        }
+    }
+
+    #[test]
+    fn test_parse_onedrive_tenant_auth_message() {
+        let message_str = r#"
+        {
+            "event_type": "onedrive_tenant_authorization",
+            "payload": {
+                "tenant_id": "72f988bf-86f1-41af-91ab-2d7cd011db47",
+                "owner_id": 123,
+                "user_id": 456,
+                "drive_user": "reports@contoso.com",
+                "cloud": "us_gov",
+                "timestamp": "2025-05-03T10:00:00Z"
+            }
+        }
+        "#;
+
+        let message = parse_message(message_str).unwrap();
+        match message {
+            MessageType::OneDriveTenantAuthorization { payload } => {
+                assert_eq!(payload.tenant_id, "72f988bf-86f1-41af-91ab-2d7cd011db47");
+                assert_eq!(payload.drive_user.as_deref(), Some("reports@contoso.com"));
+                assert!(payload.site_id.is_none());
+                assert_eq!(payload.cloud, Some(MicrosoftCloud::UsGov));
+            }
+            _ => panic!("Expected OneDriveTenantAuthorization message"),
+        }
+    }
+
     #[test]
     fn test_parse_file_sync_message() {
         let message_str = r#"
@@ -91,10 +419,101 @@ mod tests {
         let message = parse_message(message_str).unwrap();
         match message {
             MessageType::FileSync { payload } => {
-                assert_eq!(payload.bucket, "ferris-file-sync-bucket");
-                assert_eq!(payload.key, "test-file.txt");
+                assert_eq!(
+                    payload.source(),
+                    Some(FileSource::S3 {
+                        bucket: "ferris-file-sync-bucket".to_string(),
+                        key: "test-file.txt".to_string()
+                    })
+                );
             }
             _ => panic!("Expected FileSync message"),
         }
     }
+
+    #[test]
+    fn test_parse_file_sync_message_with_url_source() {
+        let message_str = r#"
+        {
+            "event_type": "file_sync",
+            "payload": {
+                "source": {
+                    "type": "url",
+                    "url": "https://files.example.com/exports/report.pdf?X-Amz-Signature=secret"
+                },
+                "destination": "/Documents/",
+                "owner_id": 123,
+                "timestamp": "2025-03-24T13:10:23Z"
+            }
+        }
+        "#;
+
+        let MessageType::FileSync { payload } = parse_message(message_str).unwrap() else {
+            panic!("Expected FileSync message");
+        };
+        let source = payload.source().unwrap();
+
+        assert_eq!(source.file_name(), Some("report.pdf"));
+        assert_eq!(source.to_string(), "https://files.example.com/exports/report.pdf");
+        assert_eq!(FileSource::Url { url: "https://example.com".into() }.file_name(), None);
+    }
+
+    #[test]
+    fn test_parse_onedrive_disconnect_message() {
+        let message_str = r#"
+        {
+            "event_type": "onedrive_disconnect",
+            "payload": {
+                "owner_id": 123,
+                "user_id": 456,
+                "timestamp": "2025-04-26T10:00:00Z"
+            }
+        }
+        "#;
+
+        let message = parse_message(message_str).unwrap();
+        match message {
+            MessageType::OneDriveDisconnect { payload } => {
+                assert_eq!(payload.owner_id, 123);
+            }
+            _ => panic!("Expected OneDriveDisconnect message"),
+        }
+    }
+
+    #[test]
+    fn test_serialized_message_parses() {
+        let message = MessageType::OneDriveDisconnect {
+            payload: OneDriveDisconnectPayload {
+                owner_id: 123,
+                user_id: 456,
+                timestamp: "2025-04-26T10:00:00Z".parse().unwrap(),
+            },
+        };
+
+        let message_str = serialize_message(&message).unwrap();
+        match parse_message(&message_str).unwrap() {
+            MessageType::OneDriveDisconnect { payload } => {
+                assert_eq!(payload.user_id, 456);
+            }
+            _ => panic!("Expected OneDriveDisconnect message"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_reauthorization_required_event() {
+        let message = Message {
+            event_type: ONEDRIVE_REAUTHORIZATION_REQUIRED.to_string(),
+            payload: OneDriveReauthorizationRequiredPayload {
+                owner_id: 123,
+                user_id: 456,
+                reason: "AADSTS50173: The provided grant has expired".to_string(),
+                timestamp: "2025-04-19T10:00:00Z".parse().unwrap(),
+            },
+        };
+
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["event_type"], "onedrive_reauthorization_required");
+        assert_eq!(value["payload"]["owner_id"], 123);
+        assert_eq!(value["payload"]["user_id"], 456);
+    }
 }
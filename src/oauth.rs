@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::{Client, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::db::models::Provider;
+use crate::db::repository::OAuthRepository;
+use crate::onedrive::RefreshLocks;
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    /// The provider rejected the refresh token (revoked access, unlinked app, expiry), so the
+    /// integration is unusable until the user authorizes again
+    #[error("{} reauthorization required: {reason}", .provider.name())]
+    ReauthorizationRequired { provider: Provider, reason: String },
+}
+
+/// Issues access tokens for a provider's integrations from the refresh tokens users granted.
+/// Shared by the providers whose integrations are kept in `oauth_integrations`.
+pub struct OAuthTokens {
+    http_client: Client,
+    provider: Provider,
+    repository: Arc<dyn OAuthRepository>,
+    refresh_locks: RefreshLocks,
+    client_id: String,
+    client_secret: String,
+    token_url: String,
+}
+
+impl OAuthTokens {
+    pub fn new(
+        provider: Provider,
+        repository: Arc<dyn OAuthRepository>,
+        refresh_locks: RefreshLocks,
+        client_id: String,
+        client_secret: String,
+        token_url: String,
+    ) -> Self {
+        let http_client = Client::new();
+
+        Self {
+            http_client,
+            provider,
+            repository,
+            refresh_locks,
+            client_id,
+            client_secret,
+            token_url,
+        }
+    }
+
+    /// Get a valid access token for a user's integration, refreshing if necessary. Refreshes
+    /// are serialized per integration, so messages arriving together share one.
+    pub async fn get_access_token(&self, owner_id: i64, user_id: i64) -> Result<String> {
+        let provider = self.provider;
+        let _user_guard = self.refresh_locks.lock(owner_id, user_id).await;
+
+        let integration = self
+            .repository
+            .get_oauth_integration(provider, owner_id, user_id)
+            .await?
+            .with_context(|| format!("No {} integration found for this user", provider.name()))?;
+
+        if !integration.is_active {
+            let reason = integration.deactivated_reason.unwrap_or_default();
+            return Err(OAuthError::ReauthorizationRequired { provider, reason }.into());
+        }
+
+        if let Some(token) =
+            self.repository.get_oauth_access_token(provider, owner_id, user_id).await?
+        {
+            return Ok(token.access_token);
+        }
+
+        let refresh_token = self
+            .repository
+            .get_oauth_refresh_token(provider, owner_id, user_id)
+            .await?
+            .with_context(|| format!("No {} refresh token found for this user", provider.name()))?;
+
+        let token_response = match self.refresh_access_token(&refresh_token).await {
+            Ok(token_response) => token_response,
+            Err(e) => {
+                if let Some(OAuthError::ReauthorizationRequired { reason, .. }) = e.downcast_ref() {
+                    println!(
+                        "Deactivating {} integration for owner {}, user {}: {}",
+                        provider.name(),
+                        owner_id,
+                        user_id,
+                        reason
+                    );
+                    self.repository
+                        .deactivate_oauth_integration(provider, owner_id, user_id, reason)
+                        .await?;
+                }
+                return Err(e);
+            }
+        };
+
+        self.repository
+            .save_oauth_access_token(
+                provider,
+                owner_id,
+                user_id,
+                &token_response.access_token,
+                access_token_expiry(&token_response),
+            )
+            .await?;
+
+        Ok(token_response.access_token)
+    }
+
+    /// Exchange a refresh token for a new access token, mapping `invalid_grant` to a typed error
+    async fn refresh_access_token(&self, refresh_token: &str) -> Result<TokenResponse> {
+        println!("Exchanging {} refresh token for access token...", self.provider.name());
+
+        let params = [
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("refresh_token", refresh_token),
+            ("grant_type", "refresh_token"),
+        ];
+
+        let response = self
+            .http_client
+            .post(&self.token_url)
+            .form(&params)
+            .send()
+            .await
+            .context("Failed to send token request")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+
+            if let Ok(error) = serde_json::from_str::<TokenErrorResponse>(&text) {
+                if error.error == "invalid_grant" {
+                    let reason = error.error_description.unwrap_or(error.error);
+                    let provider = self.provider;
+                    return Err(OAuthError::ReauthorizationRequired { provider, reason }.into());
+                }
+            }
+
+            return Err(anyhow::anyhow!("Token request failed: HTTP {}: {}", status, text));
+        }
+
+        response.json::<TokenResponse>().await.context("Failed to parse token response")
+    }
+}
+
+/// Parse the JSON body of a successful API response, or turn a failed one into an error
+pub(crate) async fn json_response<T: DeserializeOwned>(
+    response: Response,
+    action: &str,
+) -> Result<T> {
+    let response = check_response(response, action).await?;
+    response.json::<T>().await.with_context(|| format!("Failed to parse {} response", action))
+}
+
+/// Pass a successful API response through, or turn a failed one into an error
+pub(crate) async fn check_response(response: Response, action: &str) -> Result<Response> {
+    if !response.status().is_success() {
+        return Err(failed_response(response, action).await);
+    }
+
+    Ok(response)
+}
+
+/// The error for an API response that didn't have the expected status
+pub(crate) async fn failed_response(response: Response, action: &str) -> anyhow::Error {
+    let status = response.status();
+    let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+    anyhow::anyhow!("{} failed: HTTP {}: {}", action, status, text)
+}
+
+/// When a freshly issued access token should be considered expired, with a 5 minute margin
+fn access_token_expiry(token_response: &TokenResponse) -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(token_response.expires_in) - Duration::minutes(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::OAuthGrant;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_rejected_refresh_token_deactivates_integration() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("refresh_token=revoked-token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "invalid_grant",
+                "error_description": "Token has been expired or revoked."
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let grant = OAuthGrant {
+            refresh_token: "revoked-token".to_string(),
+            account_id: None,
+            granted_scopes: None,
+            authorized_at: Utc::now(),
+        };
+        repository.save_oauth_refresh_token(Provider::GoogleDrive, 123, 456, &grant).await?;
+        // The same user's Dropbox is a separate integration
+        repository.save_oauth_refresh_token(Provider::Dropbox, 123, 456, &grant).await?;
+        let tokens = OAuthTokens::new(
+            Provider::GoogleDrive,
+            repository.clone(),
+            RefreshLocks::new(),
+            "test-client-id".to_string(),
+            "test-client-secret".to_string(),
+            format!("{}/token", server.uri()),
+        );
+
+        let error = tokens.get_access_token(123, 456).await.unwrap_err();
+        assert!(error.downcast_ref::<OAuthError>().is_some());
+
+        // Known-dead integrations fail without asking the provider again
+        assert!(tokens.get_access_token(123, 456).await.is_err());
+        let integration =
+            repository.get_oauth_integration(Provider::GoogleDrive, 123, 456).await?.unwrap();
+        assert!(!integration.is_active);
+        assert_eq!(
+            integration.deactivated_reason.as_deref(),
+            Some("Token has been expired or revoked.")
+        );
+
+        let dropbox = repository.get_oauth_integration(Provider::Dropbox, 123, 456).await?;
+        assert!(dropbox.unwrap().is_active);
+
+        Ok(())
+    }
+}
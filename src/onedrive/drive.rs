@@ -0,0 +1,1235 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use reqwest::header::CONTENT_RANGE;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+
+use super::{
+    app_folder_only, drive_url, has_scope, json_response, Endpoints, OneDriveClient, OneDriveError,
+    TokenProvider, APP_FOLDER_PATH,
+};
+use crate::db::models::{OneDriveAuthMode, OneDriveIntegration};
+use crate::destination::{read_whole, Destination, DestinationPath};
+use crate::messages::{FileSyncPayload, OneDriveExportPayload};
+use crate::source::SourceFile;
+
+// Graph only accepts simple uploads up to 4 MiB, larger files go through an upload session
+const SIMPLE_UPLOAD_LIMIT: u64 = 4 * 1024 * 1024;
+
+// Upload session chunks must be a multiple of 320 KiB
+const UPLOAD_CHUNK_SIZE: u64 = 32 * 320 * 1024;
+
+// Delegated integrations are useless without write access to the user's files, or at least to
+// the app's own folder in their OneDrive
+const REQUIRED_SCOPES: [&str; 3] =
+    ["Files.ReadWrite", "Files.ReadWrite.All", "Files.ReadWrite.AppFolder"];
+
+/// Where in Microsoft 365 a synced file is written
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DriveTarget {
+    /// The integration's own drive: the user's OneDrive, or the configured user's or site's
+    /// drive for app-only integrations
+    Default,
+    Drive(String),
+    /// A SharePoint site's default document library
+    Site(String),
+    /// A Microsoft 365 group's, or team's, document library
+    Group(String),
+    /// A Teams channel's files folder, which lives in the team's document library
+    Channel {
+        group_id: String,
+        channel_id: String,
+    },
+}
+
+impl DriveTarget {
+    pub fn from_payload(payload: &FileSyncPayload) -> Result<Self> {
+        match (&payload.drive_id, &payload.site_id, &payload.group_id, &payload.channel_id) {
+            (None, None, None, None) => Ok(Self::Default),
+            (Some(drive_id), None, None, None) => Ok(Self::Drive(drive_id.clone())),
+            (None, Some(site_id), None, None) => Ok(Self::Site(site_id.clone())),
+            (None, None, Some(group_id), None) => Ok(Self::Group(group_id.clone())),
+            (None, None, Some(group_id), Some(channel_id)) => {
+                Ok(Self::Channel { group_id: group_id.clone(), channel_id: channel_id.clone() })
+            }
+            (_, _, None, Some(_)) => Err(anyhow::anyhow!("channel_id requires a group_id")),
+            _ => Err(anyhow::anyhow!("Only one of drive_id, site_id or group_id may be given")),
+        }
+    }
+}
+
+/// A file or folder to read from a drive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriveItemRef {
+    /// Path relative to the drive's root
+    Path(String),
+    Id(String),
+}
+
+impl DriveItemRef {
+    pub fn from_payload(payload: &OneDriveExportPayload) -> Result<Self> {
+        match (&payload.item_path, &payload.item_id) {
+            (Some(path), None) => Ok(Self::Path(path.clone())),
+            (None, Some(id)) => Ok(Self::Id(id.clone())),
+            _ => Err(anyhow::anyhow!("Exactly one of item_path or item_id must be given")),
+        }
+    }
+}
+
+/// A file found in a drive, ready to be downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriveFile {
+    pub drive_id: String,
+    pub id: String,
+    /// Path relative to the folder it was found in, or just its name if it was looked up itself
+    pub path: String,
+    pub size: u64,
+}
+
+/// Files created or modified in a drive since a delta query last ran
+#[derive(Debug)]
+pub struct DriveChanges {
+    /// With their path relative to the drive's root
+    pub files: Vec<DriveFile>,
+    /// Where the next delta query resumes
+    pub delta_link: String,
+}
+
+/// A file or folder in a drive
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DriveItem {
+    pub id: String,
+    pub name: String,
+    pub size: Option<u64>,
+    pub web_url: Option<String>,
+}
+
+/// The Microsoft account and drive behind an integration
+#[derive(Debug)]
+pub struct OneDriveAccount {
+    pub upn: Option<String>,
+    pub drive_id: String,
+    /// `personal`, `business` or `documentLibrary`
+    pub drive_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphUser {
+    user_principal_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphDrive {
+    id: String,
+    drive_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphAppFolder {
+    parent_reference: GraphItemReference,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphItemReference {
+    drive_id: String,
+    drive_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChannelFilesFolder {
+    id: String,
+    parent_reference: ItemReference,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ItemReference {
+    drive_id: String,
+    id: Option<String>,
+    /// e.g. `/drive/root:/Reports`. Left out of delta responses for business drives.
+    path: Option<String>,
+}
+
+/// A file or folder as Graph lists it, telling the two apart
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphItem {
+    id: String,
+    name: String,
+    size: Option<u64>,
+    folder: Option<serde_json::Value>,
+    file: Option<serde_json::Value>,
+    /// Only set on the drive's root folder
+    root: Option<serde_json::Value>,
+    /// Only set in delta responses, for items that were deleted
+    deleted: Option<serde_json::Value>,
+    parent_reference: Option<ItemReference>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphDeltaPage {
+    value: Vec<GraphItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+    #[serde(rename = "@odata.deltaLink")]
+    delta_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphItemPage {
+    value: Vec<GraphItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadSession {
+    upload_url: String,
+}
+
+/// Operations on the drives that integrations write into and export from
+#[async_trait]
+pub trait DriveOperations: Send + Sync {
+    /// Check that a user's integration is usable before any file is synced, and record the
+    /// account and drive behind it
+    async fn verify_account(&self, owner_id: i64, user_id: i64) -> Result<OneDriveAccount>;
+
+    /// Find the files in a drive item: the item itself if it's a file, or every file below it
+    /// if it's a folder
+    async fn list_files(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        item: &DriveItemRef,
+    ) -> Result<Vec<DriveFile>>;
+
+    /// Open a file found by `list_files` for reading
+    async fn download(&self, owner_id: i64, user_id: i64, file: &DriveFile) -> Result<SourceFile>;
+
+    /// Files created or modified below `folder` in a target's drive since `delta_link`. Without
+    /// one, only where changes start being tracked from is returned.
+    async fn changes(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        folder: &str,
+        delta_link: Option<&str>,
+    ) -> Result<DriveChanges>;
+}
+
+#[async_trait]
+impl DriveOperations for OneDriveClient {
+    /// Check that a user's integration is usable before any file is synced: it must have been
+    /// granted write access, and the account and drive behind it are recorded. Integrations
+    /// missing the required permission are deactivated.
+    async fn verify_account(&self, owner_id: i64, user_id: i64) -> Result<OneDriveAccount> {
+        let access_token = self.get_access_token(owner_id, user_id).await?;
+        let integration = self.get_active_integration(owner_id, user_id).await?;
+        let endpoints = self.endpoints_for(&integration);
+
+        let upn = match integration.auth_mode {
+            OneDriveAuthMode::Delegated => {
+                match integration.granted_scopes.as_deref() {
+                    Some(granted) if !REQUIRED_SCOPES.iter().any(|s| has_scope(granted, s)) => {
+                        let reason = format!(
+                            "Neither Files.ReadWrite nor Files.ReadWrite.AppFolder was granted (granted: {})",
+                            granted
+                        );
+                        self.require_reauthorization(&integration, &reason).await?;
+                        return Err(OneDriveError::ReauthorizationRequired(reason).into());
+                    }
+                    Some(_) => {}
+                    None => println!("Token response did not list granted scopes, skipping check"),
+                }
+
+                let user: GraphUser =
+                    self.graph_get(&endpoints.graph("/me"), &access_token).await?;
+                user.user_principal_name
+            }
+            // App-only tokens carry application roles instead of scopes, and have no signed-in user
+            OneDriveAuthMode::AppOnly => None,
+        };
+
+        // App-folder-only tokens can't read the drive itself, only the folder's reference to it
+        let drive = if app_folder_only(&integration) {
+            let app_folder: GraphAppFolder =
+                self.graph_get(&endpoints.graph(APP_FOLDER_PATH), &access_token).await?;
+            GraphDrive {
+                id: app_folder.parent_reference.drive_id,
+                drive_type: app_folder.parent_reference.drive_type,
+            }
+        } else {
+            self.graph_get(&drive_url(&endpoints, &integration)?, &access_token).await?
+        };
+
+        self.repository
+            .save_account_details(owner_id, user_id, upn.as_deref(), &drive.id, &drive.drive_type)
+            .await?;
+
+        Ok(OneDriveAccount { upn, drive_id: drive.id, drive_type: drive.drive_type })
+    }
+
+    /// Folders are walked breadth first, following Graph's paging through their children. Items
+    /// that are neither files nor folders, such as OneNote notebooks, are skipped.
+    async fn list_files(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        item: &DriveItemRef,
+    ) -> Result<Vec<DriveFile>> {
+        let access_token = self.get_access_token(owner_id, user_id).await?;
+        let integration = self.get_active_integration(owner_id, user_id).await?;
+        let endpoints = self.endpoints_for(&integration);
+
+        let item_url = match item {
+            DriveItemRef::Path(path) => {
+                let root_url =
+                    self.drive_root_url(&endpoints, &integration, target, &access_token).await?;
+                item_url(&root_url, path)
+            }
+            DriveItemRef::Id(id) => {
+                let base_url = drive_base_url(&endpoints, &integration, target)?;
+                format!("{}/items/{}", base_url, encode_segment(id))
+            }
+        };
+        let root: GraphItem = self.graph_get(&item_url, &access_token).await?;
+        let drive_id = root
+            .parent_reference
+            .as_ref()
+            .map(|reference| reference.drive_id.clone())
+            .context("Graph did not say which drive the item is in")?;
+
+        if root.folder.is_none() {
+            if root.file.is_none() {
+                return Err(anyhow::anyhow!("{} is neither a file nor a folder", root.name));
+            }
+            let size = root.size.unwrap_or(0);
+            return Ok(vec![DriveFile { drive_id, id: root.id, path: root.name, size }]);
+        }
+
+        let mut files = Vec::new();
+        let mut folders = VecDeque::from([(root.id, String::new())]);
+        while let Some((folder_id, folder_path)) = folders.pop_front() {
+            let mut next_url = Some(endpoints.graph(&format!(
+                "/drives/{}/items/{}/children?$select=id,name,size,folder,file",
+                drive_id, folder_id
+            )));
+
+            while let Some(url) = next_url {
+                let page: GraphItemPage = self.graph_get(&url, &access_token).await?;
+                for child in page.value {
+                    let path = if folder_path.is_empty() {
+                        child.name
+                    } else {
+                        format!("{}/{}", folder_path, child.name)
+                    };
+
+                    if child.folder.is_some() {
+                        folders.push_back((child.id, path));
+                    } else if child.file.is_some() {
+                        let size = child.size.unwrap_or(0);
+                        files.push(DriveFile {
+                            drive_id: drive_id.clone(),
+                            id: child.id,
+                            path,
+                            size,
+                        });
+                    }
+                }
+                next_url = page.next_link;
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Graph redirects to a pre-authenticated download URL, which the body is streamed from
+    async fn download(&self, owner_id: i64, user_id: i64, file: &DriveFile) -> Result<SourceFile> {
+        let access_token = self.get_access_token(owner_id, user_id).await?;
+        let integration = self.get_active_integration(owner_id, user_id).await?;
+        let endpoints = self.endpoints_for(&integration);
+
+        let response = self
+            .http_client
+            .get(endpoints.graph(&format!("/drives/{}/items/{}/content", file.drive_id, file.id)))
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .context("Failed to send download request")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+            return Err(anyhow::anyhow!(
+                "Download of {} failed: HTTP {}: {}",
+                file.path,
+                status,
+                text
+            ));
+        }
+
+        let size = response.content_length().unwrap_or(file.size);
+        let body = StreamReader::new(response.bytes_stream().map_err(std::io::Error::other));
+        Ok(SourceFile { size, body: Box::new(body) })
+    }
+
+    /// Delta queries run on the drive's root, since business drives can't run them on other
+    /// folders. Files whose path can't be found are skipped rather than failing the whole query.
+    async fn changes(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        folder: &str,
+        delta_link: Option<&str>,
+    ) -> Result<DriveChanges> {
+        let access_token = self.get_access_token(owner_id, user_id).await?;
+        let integration = self.get_active_integration(owner_id, user_id).await?;
+        let endpoints = self.endpoints_for(&integration);
+
+        let root_url = self.drive_root_url(&endpoints, &integration, target, &access_token).await?;
+        let latest = format!("{}/delta?token=latest", root_url);
+        let everything = format!("{}/delta", root_url);
+
+        let mut files = Vec::new();
+        // Paths of the folders seen so far, by ID
+        let mut folder_paths = HashMap::new();
+        let mut url = delta_link.map_or_else(|| latest.clone(), String::from);
+        loop {
+            let response = self
+                .http_client
+                .get(&url)
+                .bearer_auth(&access_token)
+                .send()
+                .await
+                .context("Failed to send delta request")?;
+
+            // Graph forgets delta tokens after a while. Changes since then can't be told apart,
+            // so every file is returned again, which is harmless since exports overwrite.
+            if response.status() == StatusCode::GONE && url != latest && url != everything {
+                println!(
+                    "Delta link expired for owner {}, user {}, enumerating the whole drive",
+                    owner_id, user_id
+                );
+                files.clear();
+                url = everything.clone();
+                continue;
+            }
+
+            let page: GraphDeltaPage = json_response(response, "Delta query").await?;
+            for item in page.value {
+                if item.deleted.is_some() {
+                    continue;
+                }
+                if item.folder.is_some() || item.root.is_some() {
+                    if let Some(path) = known_path(&item, &folder_paths) {
+                        folder_paths.insert(item.id, path);
+                    }
+                    continue;
+                }
+                if item.file.is_none() {
+                    continue;
+                }
+
+                let id = item.id.clone();
+                match self.changed_file(&endpoints, &access_token, item, &mut folder_paths).await {
+                    Ok(file) if is_below(&file.path, folder) => files.push(file),
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Skipping changed file {} for owner {}: {}", id, owner_id, e)
+                    }
+                }
+            }
+
+            match (page.next_link, page.delta_link) {
+                (Some(next_link), _) => url = next_link,
+                (None, Some(delta_link)) => return Ok(DriveChanges { files, delta_link }),
+                (None, None) => return Err(anyhow::anyhow!("Delta query returned no delta link")),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Destination for OneDriveClient {
+    /// Graph creates one folder at a time, so each one along the path is created unless it
+    /// already exists
+    async fn ensure_folder(&self, folder: &DestinationPath) -> Result<()> {
+        let (access_token, root_url) = self.destination_root(folder).await?;
+
+        let mut parent = String::new();
+        for name in folder.path.split('/').filter(|segment| !segment.is_empty()) {
+            let children_url = if parent.is_empty() {
+                format!("{}/children", root_url)
+            } else {
+                format!("{}:/{}:/children", root_url, encode_path(&parent))
+            };
+
+            let response = self
+                .http_client
+                .post(children_url)
+                .bearer_auth(&access_token)
+                .json(&serde_json::json!({
+                    "name": name,
+                    "folder": {},
+                    "@microsoft.graph.conflictBehavior": "fail"
+                }))
+                .send()
+                .await
+                .context("Failed to send create folder request")?;
+
+            if response.status() != StatusCode::CONFLICT {
+                json_response::<DriveItem>(response, "Creating folder").await?;
+            }
+
+            parent = format!("{}/{}", parent, name);
+        }
+
+        Ok(())
+    }
+
+    async fn upload(
+        &self,
+        file: &DestinationPath,
+        size: u64,
+        body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<DriveItem> {
+        let (access_token, root_url) = self.destination_root(file).await?;
+
+        self.upload_to(&access_token, &root_url, &file.path, size, body).await
+    }
+
+    async fn stat(&self, item: &DestinationPath) -> Result<Option<DriveItem>> {
+        let (access_token, root_url) = self.destination_root(item).await?;
+
+        let response = self
+            .http_client
+            .get(item_url(&root_url, &item.path))
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .context("Failed to send item request")?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        json_response(response, "Getting item").await.map(Some)
+    }
+
+    async fn delete(&self, item: &DestinationPath) -> Result<bool> {
+        if encode_path(&item.path).is_empty() {
+            return Err(anyhow::anyhow!("The destination's root folder can't be deleted"));
+        }
+        let (access_token, root_url) = self.destination_root(item).await?;
+
+        let response = self
+            .http_client
+            .delete(item_url(&root_url, &item.path))
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .context("Failed to send delete request")?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => {
+                let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+                Err(anyhow::anyhow!("Deleting item failed: HTTP {}: {}", status, text))
+            }
+        }
+    }
+}
+
+impl OneDriveClient {
+    /// Access token and Graph URL of the folder that a destination path is relative to
+    async fn destination_root(&self, item: &DestinationPath) -> Result<(String, String)> {
+        let user_id = item.user_id.context("OneDrive destinations need a user's connection")?;
+        let access_token = self.get_access_token(item.owner_id, user_id).await?;
+        let integration = self.get_active_integration(item.owner_id, user_id).await?;
+        let endpoints = self.endpoints_for(&integration);
+
+        let root_url =
+            self.drive_root_url(&endpoints, &integration, &item.target, &access_token).await?;
+
+        Ok((access_token, root_url))
+    }
+
+    /// A file found by a delta query. Where Graph left out its path, its folder is looked up,
+    /// once per folder.
+    async fn changed_file(
+        &self,
+        endpoints: &Endpoints,
+        access_token: &str,
+        item: GraphItem,
+        folder_paths: &mut HashMap<String, String>,
+    ) -> Result<DriveFile> {
+        let reference =
+            item.parent_reference.as_ref().context("Graph did not say where a file is")?;
+        let path = match known_path(&item, folder_paths) {
+            Some(path) => path,
+            None => {
+                let parent_id =
+                    reference.id.clone().context("Graph did not say where a file is")?;
+                let url = endpoints.graph(&format!(
+                    "/drives/{}/items/{}?$select=id,name,root,parentReference",
+                    reference.drive_id, parent_id
+                ));
+                let parent: GraphItem = self.graph_get(&url, access_token).await?;
+                let parent_path = known_path(&parent, folder_paths)
+                    .context("Graph did not say where a folder is")?;
+                let path = join_path(&parent_path, &item.name);
+                folder_paths.insert(parent_id, parent_path);
+                path
+            }
+        };
+
+        Ok(DriveFile {
+            drive_id: reference.drive_id.clone(),
+            id: item.id,
+            path,
+            size: item.size.unwrap_or(0),
+        })
+    }
+
+    /// Graph URL of the folder that paths in a target are relative to
+    async fn drive_root_url(
+        &self,
+        endpoints: &Endpoints,
+        integration: &OneDriveIntegration,
+        target: &DriveTarget,
+        access_token: &str,
+    ) -> Result<String> {
+        // Paths are relative to the app folder when that's all the user granted access to
+        if app_folder_only(integration) {
+            return match target {
+                DriveTarget::Default => Ok(endpoints.graph(APP_FOLDER_PATH)),
+                _ => Err(anyhow::anyhow!(
+                    "Integration only has access to its app folder, which is in the user's OneDrive"
+                )),
+            };
+        }
+
+        match target {
+            DriveTarget::Default => Ok(format!("{}/root", drive_url(endpoints, integration)?)),
+            DriveTarget::Drive(drive_id) => {
+                Ok(endpoints.graph(&format!("/drives/{}/root", drive_id)))
+            }
+            DriveTarget::Site(site_id) => {
+                Ok(endpoints.graph(&format!("/sites/{}/drive/root", site_id)))
+            }
+            DriveTarget::Group(group_id) => {
+                Ok(endpoints.graph(&format!("/groups/{}/drive/root", group_id)))
+            }
+            DriveTarget::Channel { group_id, channel_id } => {
+                let folder: ChannelFilesFolder = self
+                    .graph_get(
+                        &endpoints.graph(&format!(
+                            "/teams/{}/channels/{}/filesFolder",
+                            group_id, channel_id
+                        )),
+                        access_token,
+                    )
+                    .await
+                    .context("Failed to look up the channel's files folder")?;
+
+                Ok(endpoints.graph(&format!(
+                    "/drives/{}/items/{}",
+                    folder.parent_reference.drive_id, folder.id
+                )))
+            }
+        }
+    }
+
+    /// Upload a file below a folder URL, in one request if it's small enough and otherwise in
+    /// chunks through an upload session
+    async fn upload_to<R: AsyncRead + Unpin>(
+        &self,
+        access_token: &str,
+        root_url: &str,
+        path: &str,
+        size: u64,
+        mut body: R,
+    ) -> Result<DriveItem> {
+        let item_url = format!("{}:/{}:", root_url, encode_path(path));
+
+        if size <= SIMPLE_UPLOAD_LIMIT {
+            let content = read_whole(&mut body, size).await?;
+
+            let response = self
+                .http_client
+                .put(format!("{}/content", item_url))
+                .bearer_auth(access_token)
+                .body(content)
+                .send()
+                .await
+                .context("Failed to send upload request")?;
+
+            return json_response(response, "Upload").await;
+        }
+
+        let response = self
+            .http_client
+            .post(format!("{}/createUploadSession", item_url))
+            .bearer_auth(access_token)
+            .json(&serde_json::json!({
+                "item": { "@microsoft.graph.conflictBehavior": "replace" }
+            }))
+            .send()
+            .await
+            .context("Failed to send create upload session request")?;
+        let session: UploadSession = json_response(response, "Creating upload session").await?;
+
+        let result = self.send_chunks(&session.upload_url, size, body).await;
+        if result.is_err() {
+            // Graph keeps abandoned sessions and their chunks until they expire
+            if let Err(e) = self.cancel_upload_session(&session.upload_url).await {
+                println!("Failed to cancel upload session for {}: {:#}", path, e);
+            }
+        }
+
+        result
+    }
+
+    /// Delete an upload session along with the chunks already sent to it
+    async fn cancel_upload_session(&self, upload_url: &str) -> Result<()> {
+        let response = self
+            .http_client
+            .delete(upload_url)
+            .send()
+            .await
+            .context("Failed to send cancel upload session request")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+            return Err(anyhow::anyhow!(
+                "Cancelling upload session failed: HTTP {}: {}",
+                status,
+                text
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Send a file to an upload session in chunks, returning the item Graph created
+    async fn send_chunks<R: AsyncRead + Unpin>(
+        &self,
+        upload_url: &str,
+        size: u64,
+        mut body: R,
+    ) -> Result<DriveItem> {
+        let mut offset = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(UPLOAD_CHUNK_SIZE as usize);
+            (&mut body)
+                .take(UPLOAD_CHUNK_SIZE.min(size - offset))
+                .read_to_end(&mut chunk)
+                .await
+                .context("Failed to read file")?;
+
+            if chunk.is_empty() {
+                return Err(anyhow::anyhow!("File ended after {} of {} bytes", offset, size));
+            }
+
+            let end = offset + chunk.len() as u64;
+            // Graph only accepts ranges within the size the session was created for
+            if end == size && body.read(&mut [0u8; 1]).await.context("Failed to read file")? > 0 {
+                return Err(anyhow::anyhow!("File is longer than {} bytes", size));
+            }
+
+            // Upload URLs are pre-authenticated, so they must not be sent the access token
+            let response = self
+                .http_client
+                .put(upload_url)
+                .header(CONTENT_RANGE, format!("bytes {}-{}/{}", offset, end - 1, size))
+                .body(chunk)
+                .send()
+                .await
+                .context("Failed to send upload chunk")?;
+
+            // Graph answers the final chunk with the created item
+            if end == size {
+                return json_response(response, "Upload").await;
+            }
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let text = response.text().await.unwrap_or_else(|_| "No response body".into());
+                return Err(anyhow::anyhow!("Upload chunk failed: HTTP {}: {}", status, text));
+            }
+
+            offset = end;
+        }
+    }
+}
+
+/// Graph URL of the drive in a target, which items can be addressed by ID below
+fn drive_base_url(
+    endpoints: &Endpoints,
+    integration: &OneDriveIntegration,
+    target: &DriveTarget,
+) -> Result<String> {
+    match target {
+        DriveTarget::Default => drive_url(endpoints, integration),
+        DriveTarget::Drive(drive_id) => Ok(endpoints.graph(&format!("/drives/{}", drive_id))),
+        DriveTarget::Site(site_id) => Ok(endpoints.graph(&format!("/sites/{}/drive", site_id))),
+        DriveTarget::Group(group_id) => Ok(endpoints.graph(&format!("/groups/{}/drive", group_id))),
+        DriveTarget::Channel { .. } => {
+            Err(anyhow::anyhow!("Items in a channel's files folder must be addressed by path"))
+        }
+    }
+}
+
+/// Path of an item relative to its drive's root, given its parent's path as Graph reports it,
+/// e.g. `/drive/root:/Reports`
+fn root_relative_path(parent_path: &str, name: &str) -> String {
+    let parent = parent_path.split_once("root:").map_or("", |(_, path)| path).trim_matches('/');
+    join_path(parent, name)
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// Path of an item relative to the drive's root, if Graph or a folder seen earlier tells it
+fn known_path(item: &GraphItem, folder_paths: &HashMap<String, String>) -> Option<String> {
+    if item.root.is_some() {
+        return Some(String::new());
+    }
+
+    let reference = item.parent_reference.as_ref()?;
+    match (&reference.path, &reference.id) {
+        (Some(parent_path), _) => Some(root_relative_path(parent_path, &item.name)),
+        (None, Some(parent_id)) => {
+            folder_paths.get(parent_id).map(|parent| join_path(parent, &item.name))
+        }
+        (None, None) => None,
+    }
+}
+
+/// Whether a path relative to the drive's root is below a folder. OneDrive paths are
+/// case-insensitive.
+pub(crate) fn is_below(path: &str, folder: &str) -> bool {
+    let folder = folder.trim_matches('/').to_ascii_lowercase();
+    folder.is_empty() || path.to_ascii_lowercase().starts_with(&format!("{}/", folder))
+}
+
+/// Graph URL of an item addressed by its path below a folder URL
+fn item_url(root_url: &str, path: &str) -> String {
+    match encode_path(path) {
+        encoded if encoded.is_empty() => root_url.to_string(),
+        encoded => format!("{}:/{}", root_url, encoded),
+    }
+}
+
+/// Percent-encode a slash-separated path for path-based addressing, as in Graph and Blob Storage
+pub(crate) fn encode_path(path: &str) -> String {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(encode_segment)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Percent-encode one segment of a URL path, such as an item ID
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::Provider;
+    use crate::db::repository::IntegrationRepository;
+    use crate::onedrive::tests::test_client;
+    use chrono::Utc;
+    use std::sync::Arc;
+    use wiremock::matchers::{header, method, path, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn payload(
+        drive_id: Option<&str>,
+        group_id: Option<&str>,
+        channel_id: Option<&str>,
+    ) -> FileSyncPayload {
+        FileSyncPayload {
+            source: None,
+            bucket: Some("bucket".to_string()),
+            key: Some("report.pdf".to_string()),
+            destination: "/Reports/".to_string(),
+            provider: Provider::OneDrive,
+            owner_id: 123,
+            user_id: None,
+            drive_id: drive_id.map(String::from),
+            site_id: None,
+            group_id: group_id.map(String::from),
+            channel_id: channel_id.map(String::from),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_drive_target_from_payload() {
+        assert_eq!(
+            DriveTarget::from_payload(&payload(None, None, None)).unwrap(),
+            DriveTarget::Default
+        );
+        assert_eq!(
+            DriveTarget::from_payload(&payload(None, Some("team"), Some("general"))).unwrap(),
+            DriveTarget::Channel {
+                group_id: "team".to_string(),
+                channel_id: "general".to_string()
+            }
+        );
+        assert!(DriveTarget::from_payload(&payload(None, None, Some("general"))).is_err());
+        assert!(DriveTarget::from_payload(&payload(Some("drive"), Some("team"), None)).is_err());
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(encode_path("/Shared Reports/Q1 #2.pdf"), "Shared%20Reports/Q1%20%232.pdf");
+        assert_eq!(encode_segment("8A1B!123/../x"), "8A1B%21123%2F..%2Fx");
+    }
+
+    #[tokio::test]
+    async fn test_upload_large_file_in_chunks() -> Result<()> {
+        let server = MockServer::start().await;
+        let size = UPLOAD_CHUNK_SIZE + 1024;
+
+        Mock::given(method("POST"))
+            .and(path("/v1.0/drives/d1/root:/Reports/big.bin:/createUploadSession"))
+            .and(header("authorization", "Bearer test-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "uploadUrl": format!("{}/upload-session", server.uri())
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/upload-session"))
+            .and(header("content-range", format!("bytes 0-{}/{}", UPLOAD_CHUNK_SIZE - 1, size)))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/upload-session"))
+            .and(header(
+                "content-range",
+                format!("bytes {}-{}/{}", UPLOAD_CHUNK_SIZE, size - 1, size),
+            ))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "item-1",
+                "name": "big.bin",
+                "size": size
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server, Arc::new(InMemoryRepository::new()));
+        let root_url = client.endpoints.graph("/drives/d1/root");
+        let body = vec![0u8; size as usize];
+        let item =
+            client.upload_to("test-token", &root_url, "/Reports/big.bin", size, &body[..]).await?;
+
+        assert_eq!(item.id, "item-1");
+        assert_eq!(item.size, Some(size));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_of_wrong_size_fails_and_cancels_session() -> Result<()> {
+        let server = MockServer::start().await;
+        let size = UPLOAD_CHUNK_SIZE + 1024;
+
+        Mock::given(method("POST"))
+            .and(path("/v1.0/drives/d1/root:/Reports/big.bin:/createUploadSession"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "uploadUrl": format!("{}/upload-session", server.uri())
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/upload-session"))
+            .and(header("content-range", format!("bytes 0-{}/{}", UPLOAD_CHUNK_SIZE - 1, size)))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/upload-session"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server, Arc::new(InMemoryRepository::new()));
+        let root_url = client.endpoints.graph("/drives/d1/root");
+        let body = vec![0u8; size as usize + 1];
+        let error = client
+            .upload_to("test-token", &root_url, "/Reports/big.bin", size, &body[..])
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), format!("File is longer than {} bytes", size));
+
+        // Small files are checked before anything is sent
+        let error = client
+            .upload_to("test-token", &root_url, "/Reports/small.csv", 5, &b"a,b"[..])
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "File ended after 3 of 5 bytes");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ensure_folder_creates_missing_folders() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/v1.0/me/drive/root/children"))
+            .respond_with(ResponseTemplate::new(409))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1.0/me/drive/root:/Shared%20Reports:/children"))
+            .and(header("authorization", "Bearer cached-token"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "folder-1",
+                "name": "2025"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        repository.save_refresh_token(123, 456, "refresh-token", None, Utc::now()).await?;
+        repository.save_access_token(123, 456, "cached-token", expires_at, None).await?;
+
+        let client = test_client(&server, repository);
+        let folder = DestinationPath {
+            owner_id: 123,
+            user_id: Some(456),
+            target: DriveTarget::Default,
+            path: "/Shared Reports/2025/".to_string(),
+        };
+        client.ensure_folder(&folder).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_files_walks_folders_and_pages() -> Result<()> {
+        let server = MockServer::start().await;
+        let file = |id: &str, name: &str, size: u64| serde_json::json!({ "id": id, "name": name, "size": size, "file": {} });
+
+        Mock::given(method("GET"))
+            .and(path("/v1.0/me/drive/root:/Reports"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "folder-1",
+                "name": "Reports",
+                "folder": { "childCount": 3 },
+                "parentReference": { "driveId": "drive-1" }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/drives/drive-1/items/folder-1/children"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    file("file-1", "q1.csv", 3),
+                    { "id": "folder-2", "name": "2025", "folder": {} },
+                    { "id": "notebook-1", "name": "Notes", "package": { "type": "oneNote" } }
+                ],
+                "@odata.nextLink": format!("{}/v1.0/next-page", server.uri())
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/next-page"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [file("file-2", "q2.csv", 5)]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/drives/drive-1/items/folder-2/children"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [file("file-3", "q3.csv", 7)]
+            })))
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        repository.save_refresh_token(123, 456, "refresh-token", None, Utc::now()).await?;
+        repository.save_access_token(123, 456, "cached-token", expires_at, None).await?;
+
+        let client = test_client(&server, repository);
+        let item = DriveItemRef::Path("/Reports".to_string());
+        let mut files = client.list_files(123, 456, &DriveTarget::Default, &item).await?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let paths: Vec<_> = files.iter().map(|file| (file.path.as_str(), file.size)).collect();
+        assert_eq!(paths, vec![("2025/q3.csv", 7), ("q1.csv", 3), ("q2.csv", 5)]);
+        assert!(files.iter().all(|file| file.drive_id == "drive-1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changes_follow_pages_and_look_up_missing_paths() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/v1.0/me/drive/root/delta"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    { "id": "folder-1", "name": "Reports", "folder": {},
+                      "parentReference": { "driveId": "drive-1", "path": "/drive/root:" } },
+                    { "id": "file-1", "name": "q1.csv", "size": 3, "file": {},
+                      "parentReference": { "driveId": "drive-1", "path": "/drive/root:/Reports" } },
+                    { "id": "file-2", "name": "old.csv", "file": {}, "deleted": {},
+                      "parentReference": { "driveId": "drive-1" } },
+                    { "id": "file-4", "name": "notes.txt", "file": {},
+                      "parentReference": { "driveId": "drive-1", "path": "/drive/root:/Other" } }
+                ],
+                "@odata.nextLink": format!("{}/v1.0/delta-page-2", server.uri())
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/delta-page-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    { "id": "file-3", "name": "q2.csv", "size": 5, "file": {},
+                      "parentReference": { "driveId": "drive-1", "id": "folder-2" } },
+                    { "id": "file-5", "name": "q3.csv", "size": 7, "file": {},
+                      "parentReference": { "driveId": "drive-1", "id": "folder-2" } },
+                    { "id": "file-6", "name": "q4.csv", "size": 9, "file": {},
+                      "parentReference": { "driveId": "drive-1", "id": "folder-3" } },
+                    { "id": "file-7", "name": "q1.csv", "size": 3, "file": {},
+                      "parentReference": { "driveId": "drive-1", "id": "folder-1" } }
+                ],
+                "@odata.deltaLink": "https://graph.example/delta?token=next"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // Folders are looked up once however many files changed in them
+        Mock::given(method("GET"))
+            .and(path("/v1.0/drives/drive-1/items/folder-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "folder-2",
+                "name": "2025",
+                "parentReference": { "driveId": "drive-1", "path": "/drive/root:/Reports" }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/drives/drive-1/items/folder-3"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        repository.save_refresh_token(123, 456, "refresh-token", None, Utc::now()).await?;
+        repository.save_access_token(123, 456, "cached-token", expires_at, None).await?;
+
+        let client = test_client(&server, repository);
+        let delta_link = format!("{}/v1.0/me/drive/root/delta?token=previous", server.uri());
+        let changes =
+            client.changes(123, 456, &DriveTarget::Default, "/reports", Some(&delta_link)).await?;
+
+        let paths: Vec<_> = changes.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["Reports/q1.csv", "Reports/2025/q2.csv", "Reports/2025/q3.csv", "Reports/q1.csv"]
+        );
+        assert_eq!(changes.delta_link, "https://graph.example/delta?token=next");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_delta_link_enumerates_whole_drive() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/v1.0/me/drive/root/delta"))
+            .and(query_param("token", "expired"))
+            .respond_with(ResponseTemplate::new(410))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/me/drive/root/delta"))
+            .and(query_param_is_missing("token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    { "id": "root", "name": "root", "folder": {}, "root": {},
+                      "parentReference": { "driveId": "drive-1" } },
+                    { "id": "file-1", "name": "q1.csv", "size": 3, "file": {},
+                      "parentReference": { "driveId": "drive-1", "id": "root" } }
+                ],
+                "@odata.deltaLink": "https://graph.example/delta?token=next"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        repository.save_refresh_token(123, 456, "refresh-token", None, Utc::now()).await?;
+        repository.save_access_token(123, 456, "cached-token", expires_at, None).await?;
+
+        let client = test_client(&server, repository);
+        let delta_link = format!("{}/v1.0/me/drive/root/delta?token=expired", server.uri());
+        let changes =
+            client.changes(123, 456, &DriveTarget::Default, "/", Some(&delta_link)).await?;
+
+        let paths: Vec<_> = changes.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["q1.csv"]);
+
+        Ok(())
+    }
+}
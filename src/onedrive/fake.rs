@@ -0,0 +1,300 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::drive::{
+    is_below, DriveChanges, DriveFile, DriveItem, DriveItemRef, DriveOperations, DriveTarget,
+    OneDriveAccount,
+};
+use super::{IntegrationKey, OneDriveError, TokenProvider};
+use crate::destination::{Destination, DestinationPath};
+use crate::messages::OneDriveAuthorizationCodePayload;
+use crate::source::SourceFile;
+
+/// In-memory stand-in for Microsoft, so code built on `TokenProvider`, `DriveOperations` and
+/// `Destination` can be tested without a network or database. Every integration is usable until
+/// it's marked as needing reauthorization.
+#[derive(Default)]
+pub struct FakeDrive {
+    files: Mutex<HashMap<DestinationPath, Vec<u8>>>,
+    folders: Mutex<HashSet<DestinationPath>>,
+    /// Every upload in order, which delta links are positions in
+    uploads: Mutex<Vec<DestinationPath>>,
+    reauthorization_required: Mutex<HashSet<IntegrationKey>>,
+}
+
+impl FakeDrive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make a user's integration fail as if Microsoft had rejected its refresh token
+    pub fn require_reauthorization(&self, owner_id: i64, user_id: i64) {
+        self.reauthorization_required.lock().unwrap().insert((owner_id, user_id));
+    }
+
+    /// Contents of a file uploaded for a user's integration
+    pub fn file(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        path: &str,
+    ) -> Option<Vec<u8>> {
+        let key = DestinationPath {
+            owner_id,
+            user_id: Some(user_id),
+            target: target.clone(),
+            path: path.to_string(),
+        };
+        self.files.lock().unwrap().get(&key).cloned()
+    }
+
+    fn check_destination(&self, item: &DestinationPath) -> Result<()> {
+        let user_id = item.user_id.ok_or_else(|| anyhow::anyhow!("Fake drive needs a user"))?;
+        self.check_authorized(item.owner_id, user_id)
+    }
+
+    fn check_authorized(&self, owner_id: i64, user_id: i64) -> Result<()> {
+        if self.reauthorization_required.lock().unwrap().contains(&(owner_id, user_id)) {
+            return Err(OneDriveError::ReauthorizationRequired("Fake token expired".into()).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TokenProvider for FakeDrive {
+    async fn get_access_token(&self, owner_id: i64, user_id: i64) -> Result<String> {
+        self.check_authorized(owner_id, user_id)?;
+        Ok(format!("fake-access-token-{}-{}", owner_id, user_id))
+    }
+
+    async fn refresh_access_token_for_user(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        _valid_until: DateTime<Utc>,
+    ) -> Result<String> {
+        self.get_access_token(owner_id, user_id).await
+    }
+
+    async fn keep_refresh_token_alive(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        _refreshed_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        self.check_authorized(owner_id, user_id)?;
+        Ok(true)
+    }
+
+    async fn authorize_with_code(
+        &self,
+        payload: &OneDriveAuthorizationCodePayload,
+    ) -> Result<String> {
+        let key = (payload.owner_id, payload.user_id);
+        self.reauthorization_required.lock().unwrap().remove(&key);
+        self.get_access_token(payload.owner_id, payload.user_id).await
+    }
+}
+
+#[async_trait]
+impl DriveOperations for FakeDrive {
+    async fn verify_account(&self, owner_id: i64, user_id: i64) -> Result<OneDriveAccount> {
+        self.check_authorized(owner_id, user_id)?;
+
+        Ok(OneDriveAccount {
+            upn: Some(format!("user{}@example.com", user_id)),
+            drive_id: format!("fake-drive-{}-{}", owner_id, user_id),
+            drive_type: "business".to_string(),
+        })
+    }
+
+    /// Files uploaded into the user's own drive can be listed, with their path as their ID
+    async fn list_files(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        item: &DriveItemRef,
+    ) -> Result<Vec<DriveFile>> {
+        self.check_authorized(owner_id, user_id)?;
+        if *target != DriveTarget::Default {
+            return Err(anyhow::anyhow!("Fake drive only lists the user's own drive"));
+        }
+
+        let (DriveItemRef::Path(item_path) | DriveItemRef::Id(item_path)) = item;
+        let item_path = item_path.trim_matches('/');
+
+        let files = self.files.lock().unwrap();
+        let mut found: Vec<DriveFile> = files
+            .iter()
+            .filter(|(key, _)| key.owner_id == owner_id && key.user_id == Some(user_id))
+            .filter(|(key, _)| key.target == DriveTarget::Default)
+            .filter_map(|(key, content)| {
+                let path = key.path.trim_matches('/');
+                // A file is named by itself, files in a folder by their path below it
+                let relative = if path == item_path {
+                    path.rsplit('/').next()
+                } else if item_path.is_empty() {
+                    Some(path)
+                } else {
+                    path.strip_prefix(item_path).and_then(|rest| rest.strip_prefix('/'))
+                }?;
+
+                Some(DriveFile {
+                    drive_id: format!("fake-drive-{}-{}", owner_id, user_id),
+                    id: path.to_string(),
+                    path: relative.to_string(),
+                    size: content.len() as u64,
+                })
+            })
+            .collect();
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(found)
+    }
+
+    async fn download(&self, owner_id: i64, user_id: i64, file: &DriveFile) -> Result<SourceFile> {
+        self.check_authorized(owner_id, user_id)?;
+
+        let content = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(key, _)| {
+                key.owner_id == owner_id
+                    && key.user_id == Some(user_id)
+                    && key.target == DriveTarget::Default
+                    && key.path.trim_matches('/') == file.id
+            })
+            .map(|(_, content)| content.clone())
+            .ok_or_else(|| anyhow::anyhow!("Fake drive has no file {}", file.id))?;
+
+        Ok(SourceFile { size: content.len() as u64, body: Box::new(std::io::Cursor::new(content)) })
+    }
+
+    /// Files uploaded into the user's own drive since the delta link, which is the number of
+    /// uploads seen so far
+    async fn changes(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        target: &DriveTarget,
+        folder: &str,
+        delta_link: Option<&str>,
+    ) -> Result<DriveChanges> {
+        self.check_authorized(owner_id, user_id)?;
+        if *target != DriveTarget::Default {
+            return Err(anyhow::anyhow!("Fake drive only tracks the user's own drive"));
+        }
+
+        let uploads = self.uploads.lock().unwrap();
+        let seen = match delta_link {
+            Some(delta_link) => delta_link.parse()?,
+            None => uploads.len(),
+        };
+
+        let files = self.files.lock().unwrap();
+        let mut changed: Vec<DriveFile> = Vec::new();
+        for upload in &uploads[seen..] {
+            let path = upload.path.trim_matches('/');
+            let is_own = upload.owner_id == owner_id && upload.user_id == Some(user_id);
+            if !is_own
+                || upload.target != DriveTarget::Default
+                || !is_below(path, folder)
+                || changed.iter().any(|f| f.id == path)
+            {
+                continue;
+            }
+
+            let Some(content) = files.get(upload) else { continue };
+            changed.push(DriveFile {
+                drive_id: format!("fake-drive-{}-{}", owner_id, user_id),
+                id: path.to_string(),
+                path: path.to_string(),
+                size: content.len() as u64,
+            });
+        }
+
+        Ok(DriveChanges { files: changed, delta_link: uploads.len().to_string() })
+    }
+}
+
+#[async_trait]
+impl Destination for FakeDrive {
+    async fn ensure_folder(&self, folder: &DestinationPath) -> Result<()> {
+        self.check_destination(folder)?;
+        self.folders.lock().unwrap().insert(folder.clone());
+        Ok(())
+    }
+
+    async fn upload(
+        &self,
+        file: &DestinationPath,
+        size: u64,
+        mut body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<DriveItem> {
+        self.check_destination(file)?;
+
+        let mut content = Vec::new();
+        body.read_to_end(&mut content).await?;
+        if content.len() as u64 != size {
+            return Err(anyhow::anyhow!("Expected {} bytes, got {}", size, content.len()));
+        }
+
+        let name = file.path.rsplit('/').next().unwrap_or(&file.path).to_string();
+        let mut files = self.files.lock().unwrap();
+        files.insert(file.clone(), content);
+        self.uploads.lock().unwrap().push(file.clone());
+
+        Ok(DriveItem {
+            id: format!("fake-item-{}", files.len()),
+            name,
+            size: Some(size),
+            web_url: None,
+        })
+    }
+
+    async fn stat(&self, item: &DestinationPath) -> Result<Option<DriveItem>> {
+        self.check_destination(item)?;
+
+        let name = item.path.rsplit('/').next().unwrap_or(&item.path).to_string();
+        if let Some(content) = self.files.lock().unwrap().get(item) {
+            let size = Some(content.len() as u64);
+            return Ok(Some(DriveItem { id: item.path.clone(), name, size, web_url: None }));
+        }
+        if self.folders.lock().unwrap().contains(item) {
+            return Ok(Some(DriveItem { id: item.path.clone(), name, size: None, web_url: None }));
+        }
+
+        Ok(None)
+    }
+
+    /// Deleting a folder deletes the files below it
+    async fn delete(&self, item: &DestinationPath) -> Result<bool> {
+        self.check_destination(item)?;
+
+        let prefix = format!("{}/", item.path.trim_end_matches('/'));
+        let below = |other: &DestinationPath| {
+            other.owner_id == item.owner_id
+                && other.user_id == item.user_id
+                && other.target == item.target
+                && (other.path == item.path || other.path.starts_with(&prefix))
+        };
+
+        let mut files = self.files.lock().unwrap();
+        let mut folders = self.folders.lock().unwrap();
+        let count = files.len() + folders.len();
+        files.retain(|file, _| !below(file));
+        folders.retain(|folder| !below(folder));
+
+        Ok(files.len() + folders.len() < count)
+    }
+}
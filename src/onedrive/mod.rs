@@ -1,139 +1,881 @@
+pub mod credential;
+pub mod drive;
+#[cfg(test)]
+pub mod fake;
+pub mod refresher;
+pub mod watcher;
+
 use anyhow::{Context, Result};
-use chrono::{Duration, Utc};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::db::models::{MicrosoftCloud, OneDriveAuthMode, OneDriveIntegration};
+use crate::db::repository::IntegrationRepository;
+use crate::events::EventPublisher;
+use crate::messages::{
+    OneDriveAuthorizationCodePayload, OneDriveReauthorizationRequiredPayload,
+    ONEDRIVE_REAUTHORIZATION_REQUIRED,
+};
+use crate::oauth::json_response;
+use credential::ClientCredential;
+
+/// Microsoft identity platform and Graph endpoints, configurable per deployment for national
+/// clouds and tests. Integrations can only switch to the fixed hosts of another national cloud.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub authority_url: String,
+    pub tenant: String,
+    pub graph_url: String,
+}
 
-use crate::db;
+impl Endpoints {
+    /// Replace the tenant and cloud that are overridden, keeping the rest
+    pub fn with_overrides(&self, tenant: Option<&str>, cloud: Option<MicrosoftCloud>) -> Self {
+        let (authority_url, graph_url) = match cloud {
+            None => (self.authority_url.as_str(), self.graph_url.as_str()),
+            Some(MicrosoftCloud::Global) => {
+                ("https://login.microsoftonline.com", "https://graph.microsoft.com/v1.0")
+            }
+            Some(MicrosoftCloud::UsGov) => {
+                ("https://login.microsoftonline.us", "https://graph.microsoft.us/v1.0")
+            }
+            Some(MicrosoftCloud::China) => {
+                ("https://login.chinacloudapi.cn", "https://microsoftgraph.chinacloudapi.cn/v1.0")
+            }
+        };
+
+        Self {
+            authority_url: authority_url.to_string(),
+            tenant: tenant.unwrap_or(&self.tenant).to_string(),
+            graph_url: graph_url.to_string(),
+        }
+    }
 
-// Microsoft Graph API configuration
-const MICROSOFT_LOGIN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
+    /// The `.default` scope of the Graph host, which app-only tokens are requested for
+    fn app_scope(&self) -> Result<String> {
+        let graph_url = reqwest::Url::parse(&self.graph_url).context("Invalid Graph URL")?;
+
+        Ok(format!("{}/.default", graph_url.origin().ascii_serialization()))
+    }
+
+    fn token_url(&self) -> String {
+        format!("{}/{}/oauth2/v2.0/token", self.authority_url.trim_end_matches('/'), self.tenant)
+    }
+
+    fn graph(&self, path: &str) -> String {
+        format!("{}{}", self.graph_url.trim_end_matches('/'), path)
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct TokenResponse {
     access_token: String,
     expires_in: i64,
     refresh_token: Option<String>,
+    scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+// Scopes delegated integrations request unless the user consented to others
+const DEFAULT_SCOPES: &str = "Files.ReadWrite offline_access";
+
+/// Issues the access tokens of users' integrations and manages their authorization
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// Get a valid access token for a user's integration, refreshing if necessary
+    async fn get_access_token(&self, owner_id: i64, user_id: i64) -> Result<String>;
+
+    /// Refresh a user's access token unless the cached one is still valid at `valid_until`.
+    /// Passing a time in the future refreshes tokens ahead of demand.
+    async fn refresh_access_token_for_user(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        valid_until: DateTime<Utc>,
+    ) -> Result<String>;
+
+    /// Exchange a user's idle refresh token so it doesn't expire from inactivity, unless it was
+    /// exchanged since `refreshed_at`. Returns whether it was exchanged.
+    async fn keep_refresh_token_alive(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refreshed_at: DateTime<Utc>,
+    ) -> Result<bool>;
+
+    /// Exchange an OAuth authorization code for tokens and store them for a user
+    async fn authorize_with_code(
+        &self,
+        payload: &OneDriveAuthorizationCodePayload,
+    ) -> Result<String>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OneDriveError {
+    /// Microsoft rejected the refresh token (revoked consent, password change, expiry), so the
+    /// integration is unusable until the user authorizes again
+    #[error("OneDrive reauthorization required: {0}")]
+    ReauthorizationRequired(String),
+}
+
+/// Integrations are keyed by owner and user ID
+type IntegrationKey = (i64, i64);
+
+/// In-process locks that make sure only one task per integration talks to the token endpoint at
+/// a time. An integration's lock is dropped from the table once nobody holds or waits for it.
+/// Cloning shares the underlying lock table, so a single instance should be created at startup.
+#[derive(Clone, Default)]
+pub struct RefreshLocks {
+    locks: Arc<Mutex<HashMap<IntegrationKey, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl RefreshLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for a user's lock, holding it until the returned guard is dropped
+    pub(crate) async fn lock(&self, owner_id: i64, user_id: i64) -> RefreshGuard {
+        let key = (owner_id, user_id);
+        let user_lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            locks.entry(key).or_default().clone()
+        };
+
+        RefreshGuard { locks: self.clone(), key, guard: Some(user_lock.lock_owned().await) }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+}
+
+/// Holds an integration's refresh lock, removing it from the table on drop unless another task
+/// is waiting for it
+pub(crate) struct RefreshGuard {
+    locks: RefreshLocks,
+    key: IntegrationKey,
+    guard: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        // New waiters clone the lock while holding the table, so counting under it is race-free
+        let mut locks = self.locks.locks.lock().unwrap_or_else(|e| e.into_inner());
+        self.guard.take();
+
+        if locks.get(&self.key).is_some_and(|user_lock| Arc::strong_count(user_lock) == 1) {
+            locks.remove(&self.key);
+        }
+    }
 }
 
 pub struct OneDriveClient {
     http_client: Client,
-    pool: PgPool,
-    encryption_key: String,
+    repository: Arc<dyn IntegrationRepository>,
+    refresh_locks: RefreshLocks,
+    events: EventPublisher,
     client_id: String,
-    client_secret: String,
+    credential: ClientCredential,
+    endpoints: Endpoints,
 }
 
 impl OneDriveClient {
     pub fn new(
-        pool: PgPool,
-        encryption_key: String,
+        repository: Arc<dyn IntegrationRepository>,
+        refresh_locks: RefreshLocks,
+        events: EventPublisher,
         client_id: String,
-        client_secret: String,
+        credential: ClientCredential,
+        endpoints: Endpoints,
     ) -> Self {
         let http_client = Client::new();
 
-        Self { http_client, pool, encryption_key, client_id, client_secret }
+        Self { http_client, repository, refresh_locks, events, client_id, credential, endpoints }
     }
+}
 
-    /// Get a valid access token for an owner, refreshing if necessary
-    pub async fn get_access_token(&self, owner_id: i64) -> Result<String> {
+#[async_trait]
+impl TokenProvider for OneDriveClient {
+    /// Get a valid access token for a user's integration, refreshing if necessary
+    async fn get_access_token(&self, owner_id: i64, user_id: i64) -> Result<String> {
         // First try to get a cached, non-expired access token
-        if let Some(token) =
-            db::onedrive::get_access_token(&self.pool, owner_id, &self.encryption_key).await?
+        if let Some(token) = self.get_cached_access_token(owner_id, user_id, Utc::now()).await? {
+            return Ok(token);
+        }
+
+        self.refresh_access_token_for_user(owner_id, user_id, Utc::now()).await
+    }
+
+    /// Refresh a user's access token unless the cached one is still valid at `valid_until`.
+    /// Passing a time in the future refreshes tokens ahead of demand.
+    async fn refresh_access_token_for_user(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        valid_until: DateTime<Utc>,
+    ) -> Result<String> {
+        // Only one refresh per integration may be in flight: first within this process, then
+        // across every worker via the repository's lock, held for the duration of the refresh
+        let _user_guard = self.refresh_locks.lock(owner_id, user_id).await;
+        let refresh_lock = self.repository.lock_token_refresh(owner_id, user_id).await?;
+
+        // Whoever held the lock before us may already have refreshed the token
+        if let Some(token) = self.get_cached_access_token(owner_id, user_id, valid_until).await? {
+            return Ok(token);
+        }
+
+        println!("No sufficiently fresh access token found, refreshing...");
+        let access_token = self.refresh_holding_lock(owner_id, user_id).await?;
+        refresh_lock.release().await?;
+
+        Ok(access_token)
+    }
+
+    /// Refresh tokens exchanged since `refreshed_at` were exchanged by another worker that found
+    /// the same idle integration, and are left alone
+    async fn keep_refresh_token_alive(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        refreshed_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let _user_guard = self.refresh_locks.lock(owner_id, user_id).await;
+        let refresh_lock = self.repository.lock_token_refresh(owner_id, user_id).await?;
+
+        if self.repository.get_token_refreshed_at(owner_id, user_id).await? != Some(refreshed_at) {
+            return Ok(false);
+        }
+
+        self.refresh_holding_lock(owner_id, user_id).await?;
+        refresh_lock.release().await?;
+
+        Ok(true)
+    }
+
+    /// Exchange an OAuth authorization code for tokens and store them for a user, so that
+    /// refresh tokens never have to leave this service. The payload carries the PKCE verifier
+    /// matching the challenge the product sent to the authorize endpoint.
+    async fn authorize_with_code(
+        &self,
+        payload: &OneDriveAuthorizationCodePayload,
+    ) -> Result<String> {
+        println!("Exchanging authorization code for tokens...");
+
+        let owner_id = payload.owner_id;
+        let user_id = payload.user_id;
+        let endpoints = self.endpoints.with_overrides(payload.tenant_id.as_deref(), payload.cloud);
+
+        let params = [
+            ("code", payload.code.as_str()),
+            ("redirect_uri", payload.redirect_uri.as_str()),
+            ("code_verifier", payload.code_verifier.as_str()),
+            ("grant_type", "authorization_code"),
+            ("scope", payload.scope.as_deref().unwrap_or(DEFAULT_SCOPES)),
+        ];
+
+        // Hold the refresh lock so an in-flight refresh can't overwrite the new tokens
+        let _user_guard = self.refresh_locks.lock(owner_id, user_id).await;
+        let refresh_lock = self.repository.lock_token_refresh(owner_id, user_id).await?;
+
+        // Codes can only be redeemed once, don't use one up for a message that would be ignored
+        let integration = match self.repository.get_integration(owner_id, user_id).await? {
+            Some(integration) => Some(integration),
+            None => self.repository.get_deactivated_integration(owner_id, user_id).await?,
+        };
+        if integration
+            .and_then(|integration| integration.authorized_at)
+            .is_some_and(|authorized_at| authorized_at >= payload.timestamp)
         {
-            println!("Found existing access token valid until {}", token.expires_at);
-            return Ok(token.access_token);
+            return Err(anyhow::anyhow!(
+                "Ignoring authorization code from {}, a newer authorization exists",
+                payload.timestamp
+            ));
         }
 
-        println!("No valid access token found, refreshing...");
+        let token_response = self.request_token(&endpoints, &params).await?;
 
-        // No valid access token found, get the refresh token and use it to get a new access token
-        let refresh_token = self.get_refresh_token(owner_id).await?;
+        let refresh_token = token_response.refresh_token.as_deref().context(
+            "Token response did not include a refresh token, is offline_access granted?",
+        )?;
 
-        // Exchange refresh token for a new access token
-        let token_response = self.refresh_access_token(&refresh_token).await?;
+        let saved = self
+            .repository
+            .save_refresh_token(
+                owner_id,
+                user_id,
+                refresh_token,
+                payload.scope.as_deref(),
+                payload.timestamp,
+            )
+            .await?;
+
+        if saved.is_none() {
+            return Err(anyhow::anyhow!(
+                "Ignoring authorization code from {}, a newer authorization exists",
+                payload.timestamp
+            ));
+        }
+
+        self.repository
+            .save_endpoint_overrides(owner_id, user_id, payload.tenant_id.as_deref(), payload.cloud)
+            .await?;
+
+        self.repository
+            .save_access_token(
+                owner_id,
+                user_id,
+                &token_response.access_token,
+                access_token_expiry(&token_response),
+                token_response.scope.as_deref(),
+            )
+            .await?;
 
-        // Calculate expiry time (subtract 5 minutes for safety margin)
-        let expires_at =
-            Utc::now() + Duration::seconds(token_response.expires_in) - Duration::minutes(5);
+        refresh_lock.release().await?;
+
+        Ok(token_response.access_token)
+    }
+}
+
+impl OneDriveClient {
+    /// Send a GET request to Graph and parse the JSON response
+    async fn graph_get<T: DeserializeOwned>(&self, url: &str, access_token: &str) -> Result<T> {
+        let response = self
+            .http_client
+            .get(url)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .context("Failed to send Graph request")?;
+
+        json_response(response, "Graph request").await
+    }
+
+    /// Exchange a user's refresh token, or request an app-only token, and save the result. The
+    /// caller must hold the integration's refresh locks.
+    async fn refresh_holding_lock(&self, owner_id: i64, user_id: i64) -> Result<String> {
+        let integration = self.get_active_integration(owner_id, user_id).await?;
+        let endpoints = self.endpoints_for(&integration);
+
+        let token_response = match integration.auth_mode {
+            OneDriveAuthMode::Delegated => {
+                // Get the refresh token and exchange it for a new access token
+                let refresh_token = self.get_refresh_token(owner_id, user_id).await?;
+
+                let scope = integration.requested_scopes.as_deref().unwrap_or(DEFAULT_SCOPES);
+
+                match self.refresh_access_token(&endpoints, &refresh_token, scope).await {
+                    Ok(token_response) => token_response,
+                    Err(e) => {
+                        if let Some(OneDriveError::ReauthorizationRequired(reason)) =
+                            e.downcast_ref()
+                        {
+                            self.require_reauthorization(&integration, reason).await?;
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            OneDriveAuthMode::AppOnly => {
+                if integration.tenant_id.is_none() {
+                    return Err(anyhow::anyhow!("App-only OneDrive integration has no tenant"));
+                }
+
+                self.request_app_token(&endpoints).await?
+            }
+        };
+
+        let expires_at = access_token_expiry(&token_response);
 
         println!("Obtained new access token valid until {}", expires_at);
 
         // Save the new access token
-        db::onedrive::save_access_token(
-            &self.pool,
-            owner_id,
-            &token_response.access_token,
-            expires_at,
-            &self.encryption_key,
-        )
-        .await?;
+        self.repository
+            .save_access_token(
+                owner_id,
+                user_id,
+                &token_response.access_token,
+                expires_at,
+                token_response.scope.as_deref(),
+            )
+            .await?;
 
         // If we got a new refresh token, update it too
         if let Some(new_refresh_token) = token_response.refresh_token {
             println!("Received new refresh token, updating...");
 
-            // Get the user_id from the existing integration
-            let integration = db::onedrive::get_integration(&self.pool, owner_id)
-                .await?
-                .context("No OneDrive integration found for this owner")?;
-
-            db::onedrive::save_refresh_token(
-                &self.pool,
-                owner_id,
-                integration.user_id,
-                &new_refresh_token,
-                &self.encryption_key,
-            )
-            .await?;
+            self.repository.rotate_refresh_token(owner_id, user_id, &new_refresh_token).await?;
         }
 
         Ok(token_response.access_token)
     }
 
-    /// Get the refresh token for an owner
-    async fn get_refresh_token(&self, owner_id: i64) -> Result<String> {
-        let refresh_token =
-            db::onedrive::get_refresh_token(&self.pool, owner_id, &self.encryption_key)
-                .await?
-                .context("No OneDrive refresh token found for this owner")?;
+    /// Get a cached access token for a user if one exists that is still valid at `valid_until`
+    async fn get_cached_access_token(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+        valid_until: DateTime<Utc>,
+    ) -> Result<Option<String>> {
+        let token = self.repository.get_access_token(owner_id, user_id).await?;
+
+        Ok(token.filter(|token| token.expires_at > valid_until).map(|token| {
+            println!("Found existing access token valid until {}", token.expires_at);
+            token.access_token
+        }))
+    }
+
+    /// The endpoints of the deployment with an integration's overrides applied
+    fn endpoints_for(&self, integration: &OneDriveIntegration) -> Endpoints {
+        self.endpoints.with_overrides(integration.tenant_id.as_deref(), integration.cloud)
+    }
+
+    /// Get the active integration of a user
+    async fn get_active_integration(
+        &self,
+        owner_id: i64,
+        user_id: i64,
+    ) -> Result<OneDriveIntegration> {
+        if let Some(integration) = self.repository.get_integration(owner_id, user_id).await? {
+            return Ok(integration);
+        }
+
+        // Don't keep failing with a generic error for integrations we already know are dead
+        if let Some(reason) = self
+            .repository
+            .get_deactivated_integration(owner_id, user_id)
+            .await?
+            .and_then(|integration| integration.deactivated_reason)
+        {
+            return Err(OneDriveError::ReauthorizationRequired(reason).into());
+        }
+
+        Err(anyhow::anyhow!("No OneDrive integration found for this user"))
+    }
+
+    /// Get the refresh token of a user's integration
+    async fn get_refresh_token(&self, owner_id: i64, user_id: i64) -> Result<String> {
+        let refresh_token = self
+            .repository
+            .get_refresh_token(owner_id, user_id)
+            .await?
+            .context("No OneDrive refresh token found for this user")?;
 
         Ok(refresh_token.refresh_token)
     }
 
+    /// Deactivate an integration after its refresh token was rejected and let the product know
+    /// the user has to connect OneDrive again
+    async fn require_reauthorization(
+        &self,
+        integration: &OneDriveIntegration,
+        reason: &str,
+    ) -> Result<()> {
+        let (owner_id, user_id) = (integration.owner_id, integration.user_id);
+
+        println!(
+            "Deactivating OneDrive integration for owner {}, user {}: {}",
+            owner_id, user_id, reason
+        );
+
+        self.repository.deactivate_integration(owner_id, user_id, reason).await?;
+
+        self.events
+            .publish(
+                ONEDRIVE_REAUTHORIZATION_REQUIRED,
+                OneDriveReauthorizationRequiredPayload {
+                    owner_id,
+                    user_id,
+                    reason: reason.to_string(),
+                    timestamp: Utc::now(),
+                },
+            )
+            .await
+    }
+
     /// Exchange a refresh token for a new access token
-    async fn refresh_access_token(&self, refresh_token: &str) -> Result<TokenResponse> {
+    async fn refresh_access_token(
+        &self,
+        endpoints: &Endpoints,
+        refresh_token: &str,
+        scope: &str,
+    ) -> Result<TokenResponse> {
         println!("Exchanging refresh token for access token...");
 
-        let params = [
-            ("client_id", self.client_id.as_str()),
-            ("client_secret", self.client_secret.as_str()),
-            ("refresh_token", refresh_token),
-            ("grant_type", "refresh_token"),
-            ("scope", "Files.ReadWrite offline_access"),
-        ];
+        let params =
+            [("refresh_token", refresh_token), ("grant_type", "refresh_token"), ("scope", scope)];
+
+        let token_data = self.request_token(endpoints, &params).await?;
+
+        println!("Successfully refreshed access token");
+
+        Ok(token_data)
+    }
+
+    /// Get an app-only access token for a tenant using the client credentials grant
+    async fn request_app_token(&self, endpoints: &Endpoints) -> Result<TokenResponse> {
+        println!("Requesting app-only access token for tenant {}...", endpoints.tenant);
+
+        let scope = endpoints.app_scope()?;
+        let params = [("grant_type", "client_credentials"), ("scope", scope.as_str())];
+
+        self.request_token(endpoints, &params).await
+    }
+
+    /// Send an authenticated request to a tenant's token endpoint, mapping `invalid_grant` to a
+    /// typed error
+    async fn request_token(
+        &self,
+        endpoints: &Endpoints,
+        params: &[(&str, &str)],
+    ) -> Result<TokenResponse> {
+        let token_url = endpoints.token_url();
+
+        let mut form = vec![("client_id", self.client_id.clone())];
+        form.extend(self.credential.token_request_params(&self.client_id, &token_url)?);
+        form.extend(params.iter().map(|(name, value)| (*name, value.to_string())));
 
         let response = self
             .http_client
-            .post(MICROSOFT_LOGIN_URL)
-            .form(&params)
+            .post(&token_url)
+            .form(&form)
             .send()
             .await
-            .context("Failed to send refresh token request")?;
+            .context("Failed to send token request")?;
 
         if !response.status().is_success() {
             let status = response.status();
             let text = response.text().await.unwrap_or_else(|_| "No response body".into());
-            return Err(anyhow::anyhow!("Token refresh failed: HTTP {}: {}", status, text));
+
+            if let Ok(error) = serde_json::from_str::<TokenErrorResponse>(&text) {
+                if error.error == "invalid_grant" {
+                    let reason = error.error_description.unwrap_or(error.error);
+                    return Err(OneDriveError::ReauthorizationRequired(reason).into());
+                }
+            }
+
+            return Err(anyhow::anyhow!("Token request failed: HTTP {}: {}", status, text));
         }
 
-        let token_data =
-            response.json::<TokenResponse>().await.context("Failed to parse token response")?;
+        response.json::<TokenResponse>().await.context("Failed to parse token response")
+    }
+}
+
+// The folder in the user's OneDrive reserved for this app
+const APP_FOLDER_PATH: &str = "/me/drive/special/approot";
 
-        println!("Successfully refreshed access token");
+/// Graph URL of the drive an integration writes into. Delegated integrations use the signed-in
+/// user's OneDrive, app-only ones the configured site's document library or user's OneDrive.
+fn drive_url(endpoints: &Endpoints, integration: &OneDriveIntegration) -> Result<String> {
+    match integration.auth_mode {
+        OneDriveAuthMode::Delegated => Ok(endpoints.graph("/me/drive")),
+        OneDriveAuthMode::AppOnly => match (&integration.drive_site_id, &integration.drive_user) {
+            (Some(site_id), _) => Ok(endpoints.graph(&format!("/sites/{}/drive", site_id))),
+            (None, Some(user)) => Ok(endpoints.graph(&format!("/users/{}/drive", user))),
+            (None, None) => Err(anyhow::anyhow!("App-only OneDrive integration has no drive")),
+        },
+    }
+}
 
-        Ok(token_data)
+/// Whether a delegated integration may only write into the app's own folder in the user's
+/// OneDrive, because the user refused full drive access
+fn app_folder_only(integration: &OneDriveIntegration) -> bool {
+    if integration.auth_mode != OneDriveAuthMode::Delegated {
+        return false;
     }
+
+    // Until Microsoft reports the granted scopes, go by what the user was asked for
+    let scopes = match integration.granted_scopes.as_deref() {
+        Some(granted) => granted,
+        None => integration.requested_scopes.as_deref().unwrap_or(DEFAULT_SCOPES),
+    };
+
+    has_scope(scopes, "Files.ReadWrite.AppFolder")
+        && !has_scope(scopes, "Files.ReadWrite")
+        && !has_scope(scopes, "Files.ReadWrite.All")
 }
 
+/// Whether a space-separated scope list contains a scope, which Microsoft may prefix with the
+/// resource URI
+fn has_scope(granted: &str, scope: &str) -> bool {
+    granted
+        .split_whitespace()
+        .any(|granted| granted.rsplit('/').next().unwrap_or(granted).eq_ignore_ascii_case(scope))
+}
+
+/// Calculate when a freshly issued access token should be considered expired
+/// (subtract 5 minutes for safety margin)
+fn access_token_expiry(token_response: &TokenResponse) -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(token_response.expires_in) - Duration::minutes(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_refresh_locks_are_dropped_when_released() {
+        let locks = RefreshLocks::new();
+
+        let first = locks.lock(123, 456).await;
+        let waiting = tokio::spawn({
+            let locks = locks.clone();
+            async move { drop(locks.lock(123, 456).await) }
+        });
+        tokio::task::yield_now().await;
+        drop(first);
+
+        // The waiting task still needed the lock, so it survived the first release
+        waiting.await.unwrap();
+        assert_eq!(locks.len(), 0);
+    }
+
+    pub(crate) fn test_client(
+        server: &MockServer,
+        repository: Arc<InMemoryRepository>,
+    ) -> OneDriveClient {
+        let sqs_config = aws_sdk_sqs::Config::builder()
+            .behavior_version(aws_sdk_sqs::config::BehaviorVersion::latest())
+            .region(aws_sdk_sqs::config::Region::new("us-east-1"))
+            .build();
+
+        OneDriveClient::new(
+            repository,
+            RefreshLocks::new(),
+            EventPublisher::new(aws_sdk_sqs::Client::from_conf(sqs_config), None),
+            "test-client-id".to_string(),
+            ClientCredential::Secret("test-client-secret".to_string()),
+            Endpoints {
+                authority_url: server.uri(),
+                tenant: "test-tenant".to_string(),
+                graph_url: format!("{}/v1.0", server.uri()),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_refresh_access_token() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/test-tenant/oauth2/v2.0/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .and(body_string_contains("client_secret=test-client-secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "new-access-token",
+                "expires_in": 3600,
+                "refresh_token": "new-refresh-token"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server, Arc::new(InMemoryRepository::new()));
+        let token = client
+            .refresh_access_token(&client.endpoints, "old-refresh-token", DEFAULT_SCOPES)
+            .await?;
+
+        assert_eq!(token.access_token, "new-access-token");
+        assert_eq!(token.refresh_token.as_deref(), Some("new-refresh-token"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_access_token_invalid_grant() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/test-tenant/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "invalid_grant",
+                "error_description": "AADSTS70008: The refresh token has expired"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server, Arc::new(InMemoryRepository::new()));
+        let error = client
+            .refresh_access_token(&client.endpoints, "expired-refresh-token", DEFAULT_SCOPES)
+            .await
+            .unwrap_err();
+
+        match error.downcast_ref() {
+            Some(OneDriveError::ReauthorizationRequired(reason)) => {
+                assert!(reason.starts_with("AADSTS70008"));
+            }
+            None => panic!("Expected ReauthorizationRequired, got: {}", error),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_access_token_refreshes_once_and_rotates() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/test-tenant/oauth2/v2.0/token"))
+            .and(body_string_contains("refresh_token=old-refresh-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "new-access-token",
+                "expires_in": 3600,
+                "refresh_token": "new-refresh-token",
+                "scope": "Files.ReadWrite"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        repository.save_refresh_token(1, 2, "old-refresh-token", None, Utc::now()).await?;
+        let client = test_client(&server, repository.clone());
+
+        // The second call is served from the cache
+        assert_eq!(client.get_access_token(1, 2).await?, "new-access-token");
+        assert_eq!(client.get_access_token(1, 2).await?, "new-access-token");
+
+        let refresh_token = repository.get_refresh_token(1, 2).await?.unwrap();
+        assert_eq!(refresh_token.refresh_token, "new-refresh-token");
+        let integration = repository.get_integration(1, 2).await?.unwrap();
+        assert_eq!(integration.granted_scopes.as_deref(), Some("Files.ReadWrite"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_skips_tokens_exchanged_by_another_worker() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/test-tenant/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "new-access-token",
+                "expires_in": 3600,
+                "refresh_token": "new-refresh-token"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        repository.save_refresh_token(1, 2, "old-refresh-token", None, Utc::now()).await?;
+        let client = test_client(&server, repository.clone());
+
+        // Both workers selected the idle token at the same time, only the first exchanges it
+        let selected_at = repository.get_token_refreshed_at(1, 2).await?.unwrap();
+        assert!(client.keep_refresh_token_alive(1, 2, selected_at).await?);
+        assert!(!client.keep_refresh_token_alive(1, 2, selected_at).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_authorization_code_is_not_redeemed() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/test-tenant/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let authorized_at = Utc::now();
+        repository.save_refresh_token(1, 2, "newer-refresh-token", None, authorized_at).await?;
+        let client = test_client(&server, repository.clone());
+
+        let payload = OneDriveAuthorizationCodePayload {
+            code: "stale-code".to_string(),
+            redirect_uri: "https://app.example.com/onedrive/callback".to_string(),
+            code_verifier: "verifier".to_string(),
+            owner_id: 1,
+            user_id: 2,
+            tenant_id: None,
+            scope: None,
+            cloud: None,
+            timestamp: authorized_at - Duration::minutes(1),
+        };
+        assert!(client.authorize_with_code(&payload).await.is_err());
+
+        let refresh_token = repository.get_refresh_token(1, 2).await?.unwrap();
+        assert_eq!(refresh_token.refresh_token, "newer-refresh-token");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_refresh_token_deactivates_integration() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/test-tenant/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "invalid_grant",
+                "error_description": "AADSTS50173: The grant was revoked"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        repository.save_refresh_token(1, 2, "revoked-refresh-token", None, Utc::now()).await?;
+        let client = test_client(&server, repository.clone());
+
+        // Later calls fail from the stored reason without asking Microsoft again
+        for _ in 0..2 {
+            let error = client.get_access_token(1, 2).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref(),
+                Some(OneDriveError::ReauthorizationRequired(_))
+            ));
+        }
+
+        assert!(repository.get_integration(1, 2).await?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_scope() {
+        let granted = "https://graph.microsoft.com/Files.ReadWrite User.Read openid";
+
+        assert!(has_scope(granted, "Files.ReadWrite"));
+        assert!(has_scope(granted, "user.read"));
+        assert!(!has_scope(granted, "Files.ReadWrite.All"));
+        assert!(!has_scope("Files.Read", "Files.ReadWrite"));
+    }
+
+    #[test]
+    fn test_endpoint_overrides() {
+        let endpoints = Endpoints {
+            authority_url: "https://login.microsoftonline.com".to_string(),
+            tenant: "common".to_string(),
+            graph_url: "https://graph.microsoft.com/v1.0".to_string(),
+        };
+
+        let overridden =
+            endpoints.with_overrides(Some("contoso.onmicrosoft.us"), Some(MicrosoftCloud::UsGov));
+
+        assert_eq!(
+            overridden.token_url(),
+            "https://login.microsoftonline.us/contoso.onmicrosoft.us/oauth2/v2.0/token"
+        );
+        assert_eq!(overridden.graph("/me/drive"), "https://graph.microsoft.us/v1.0/me/drive");
+        assert_eq!(overridden.app_scope().unwrap(), "https://graph.microsoft.us/.default");
+
+        let default = endpoints.with_overrides(None, None);
+        assert_eq!(
+            default.token_url(),
+            "https://login.microsoftonline.com/common/oauth2/v2.0/token"
+        );
+        assert_eq!(default.app_scope().unwrap(), "https://graph.microsoft.com/.default");
+    }
+}
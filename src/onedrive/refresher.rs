@@ -0,0 +1,93 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+use super::TokenProvider;
+use crate::db::repository::IntegrationRepository;
+
+/// Background task that keeps OneDrive tokens warm so syncs don't pay for a refresh, and so
+/// refresh tokens of integrations that sync rarely are exercised before they expire from
+/// inactivity
+pub struct TokenRefresher {
+    client: Arc<dyn TokenProvider>,
+    repository: Arc<dyn IntegrationRepository>,
+    interval: std::time::Duration,
+    lead: Duration,
+    keepalive: Duration,
+}
+
+impl TokenRefresher {
+    pub fn new(
+        client: Arc<dyn TokenProvider>,
+        repository: Arc<dyn IntegrationRepository>,
+        interval: std::time::Duration,
+        lead: Duration,
+        keepalive: Duration,
+    ) -> Self {
+        Self { client, repository, interval, lead, keepalive }
+    }
+
+    /// Run refresh passes forever, one per interval
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.refresh_pass().await {
+                println!("Token refresh pass failed: {}", e);
+            }
+        }
+    }
+
+    /// Refresh every integration whose access token is about to expire or whose refresh token
+    /// has been idle for too long
+    async fn refresh_pass(&self) -> Result<()> {
+        let now = Utc::now();
+        let candidates = self
+            .repository
+            .get_token_refresh_candidates(now + self.lead, now - self.keepalive)
+            .await?;
+
+        for candidate in candidates {
+            println!(
+                "Proactively refreshing OneDrive token for owner: {}, user: {}",
+                candidate.owner_id, candidate.user_id
+            );
+
+            // Idle refresh tokens must be exercised even if the cached access token is still valid
+            let refreshed = if candidate.refresh_token_idle {
+                self.client
+                    .keep_refresh_token_alive(
+                        candidate.owner_id,
+                        candidate.user_id,
+                        candidate.token_refreshed_at,
+                    )
+                    .await
+                    .map(|exchanged| {
+                        if !exchanged {
+                            println!("Refresh token was already exchanged by another worker");
+                        }
+                    })
+            } else {
+                self.client
+                    .refresh_access_token_for_user(
+                        candidate.owner_id,
+                        candidate.user_id,
+                        now + self.lead,
+                    )
+                    .await
+                    .map(|_| ())
+            };
+
+            if let Err(e) = refreshed {
+                println!(
+                    "Failed to refresh OneDrive token for owner {}, user {}: {}",
+                    candidate.owner_id, candidate.user_id, e
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::Result;
+use chrono::Utc;
+use std::sync::Arc;
+
+use super::drive::{is_below, DriveFile, DriveOperations, DriveTarget};
+use crate::db::models::OneDriveWatch;
+use crate::db::repository::WatchRepository;
+use crate::events::EventPublisher;
+use crate::export::export_key;
+use crate::messages::OneDriveExportPayload;
+
+/// Background task that polls watched OneDrive folders with delta queries and queues an export
+/// of every file created or modified in them
+pub struct FolderWatcher {
+    drive: Arc<dyn DriveOperations>,
+    repository: Arc<dyn WatchRepository>,
+    /// Publishes to the queue the worker consumes
+    queue: EventPublisher,
+    interval: std::time::Duration,
+}
+
+impl FolderWatcher {
+    pub fn new(
+        drive: Arc<dyn DriveOperations>,
+        repository: Arc<dyn WatchRepository>,
+        queue: EventPublisher,
+        interval: std::time::Duration,
+    ) -> Self {
+        Self { drive, repository, queue, interval }
+    }
+
+    /// Run poll passes forever, one per interval
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.poll_pass().await {
+                println!("Watched folder poll pass failed: {}", e);
+            }
+        }
+    }
+
+    /// Poll every watch whose integration is active and that no worker polled during this
+    /// interval. Claiming a watch marks it polled, so with several workers each change is only
+    /// exported once. Delta links are only saved once the exports were queued, so a failed poll
+    /// is retried from the same point next interval.
+    async fn poll_pass(&self) -> Result<()> {
+        // Passes are a little under an interval apart, so half of one is enough to tell whether
+        // a watch was already polled during this one
+        let due_before = Utc::now() - chrono::Duration::from_std(self.interval / 2)?;
+        for watch in self.repository.claim_due_onedrive_watches(due_before).await? {
+            if let Err(e) = self.poll(&watch).await {
+                println!(
+                    "Failed to poll watched folder {} for owner {}, user {}: {}",
+                    watch.folder_path, watch.owner_id, watch.user_id, e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn poll(&self, watch: &OneDriveWatch) -> Result<()> {
+        let (exports, delta_link) = watched_changes(self.drive.as_ref(), watch).await?;
+
+        for export in exports {
+            self.queue.publish("onedrive_export", export).await?;
+        }
+
+        if !self
+            .repository
+            .save_onedrive_delta_link(watch.id, watch.registered_at, &delta_link)
+            .await?
+        {
+            println!("Watch {} was registered again while it was polled", watch.id);
+        }
+
+        Ok(())
+    }
+}
+
+/// Exports of the files created or modified in a watched folder since it was last polled, and
+/// where the next poll resumes
+pub async fn watched_changes(
+    drive: &dyn DriveOperations,
+    watch: &OneDriveWatch,
+) -> Result<(Vec<OneDriveExportPayload>, String)> {
+    let target = match &watch.drive_id {
+        Some(drive_id) => DriveTarget::Drive(drive_id.clone()),
+        None => DriveTarget::Default,
+    };
+    let changes = drive
+        .changes(
+            watch.owner_id,
+            watch.user_id,
+            &target,
+            &watch.folder_path,
+            watch.delta_link.as_deref(),
+        )
+        .await?;
+
+    let exports: Vec<_> = changes.files.iter().filter_map(|file| export_for(watch, file)).collect();
+    if !exports.is_empty() {
+        println!(
+            "Found {} changed files in {} for owner: {}, user: {}",
+            exports.len(),
+            watch.folder_path,
+            watch.owner_id,
+            watch.user_id
+        );
+    }
+
+    Ok((exports, changes.delta_link))
+}
+
+/// Export of a changed file if it's below the watched folder, keeping its path below the folder
+/// under the watch's prefix
+fn export_for(watch: &OneDriveWatch, file: &DriveFile) -> Option<OneDriveExportPayload> {
+    let folder = watch.folder_path.trim_matches('/');
+    let relative = if folder.is_empty() {
+        file.path.as_str()
+    } else {
+        is_below(&file.path, folder).then(|| &file.path[folder.len() + 1..])?
+    };
+    let parent = relative.rsplit_once('/').map_or("", |(parent, _)| parent);
+
+    Some(OneDriveExportPayload {
+        owner_id: watch.owner_id,
+        user_id: Some(watch.user_id),
+        item_path: None,
+        item_id: Some(file.id.clone()),
+        drive_id: watch.drive_id.clone(),
+        bucket: watch.bucket.clone(),
+        prefix: export_key(&watch.prefix, parent),
+        timestamp: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use crate::db::models::WatchedFolder;
+    use crate::db::repository::IntegrationRepository;
+    use crate::destination::{Destination, DestinationPath};
+    use crate::onedrive::fake::FakeDrive;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_changes_below_watched_folder_are_exported() -> Result<()> {
+        let repository = InMemoryRepository::new();
+        let drive = FakeDrive::new();
+        repository.save_refresh_token(123, 456, "refresh-token", None, Utc::now()).await?;
+
+        let folder = WatchedFolder {
+            folder_path: "/Reports".to_string(),
+            drive_id: None,
+            bucket: "exports-bucket".to_string(),
+            prefix: "backup".to_string(),
+        };
+        repository.save_onedrive_watch(123, 456, &folder, Utc::now()).await?;
+
+        // The first poll only records where changes are tracked from
+        let due_before = Utc::now() + chrono::Duration::seconds(1);
+        let watch = repository.claim_due_onedrive_watches(due_before).await?.remove(0);
+        let (exports, delta_link) = watched_changes(&drive, &watch).await?;
+        assert!(exports.is_empty());
+        repository.save_onedrive_delta_link(watch.id, watch.registered_at, &delta_link).await?;
+
+        for path in ["/reports/2025/q1.csv", "/Reports Archive/q0.csv", "/Other/notes.txt"] {
+            let file = DestinationPath {
+                owner_id: 123,
+                user_id: Some(456),
+                target: DriveTarget::Default,
+                path: path.to_string(),
+            };
+            drive.upload(&file, 3, Box::new(std::io::Cursor::new(b"a,b".to_vec()))).await?;
+        }
+
+        let due_before = Utc::now() + chrono::Duration::seconds(1);
+        let watch = repository.claim_due_onedrive_watches(due_before).await?.remove(0);
+        let (exports, _) = watched_changes(&drive, &watch).await?;
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].item_id.as_deref(), Some("reports/2025/q1.csv"));
+        assert_eq!(exports[0].prefix, "backup/2025");
+        assert_eq!(exports[0].user_id, Some(456));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_is_polled_by_one_watcher() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "MessageId": "message-1"
+            })))
+            .mount(&server)
+            .await;
+
+        let repository = Arc::new(InMemoryRepository::new());
+        let drive = Arc::new(FakeDrive::new());
+        repository.save_refresh_token(123, 456, "refresh-token", None, Utc::now()).await?;
+        let folder = WatchedFolder {
+            folder_path: "/Reports".to_string(),
+            drive_id: None,
+            bucket: "exports-bucket".to_string(),
+            prefix: String::new(),
+        };
+        repository.save_onedrive_watch(123, 456, &folder, Utc::now()).await?;
+        let due_before = Utc::now() + chrono::Duration::seconds(1);
+        let watch = repository.claim_due_onedrive_watches(due_before).await?.remove(0);
+        let (_, delta_link) = watched_changes(drive.as_ref(), &watch).await?;
+        repository.save_onedrive_delta_link(watch.id, watch.registered_at, &delta_link).await?;
+
+        let file = DestinationPath {
+            owner_id: 123,
+            user_id: Some(456),
+            target: DriveTarget::Default,
+            path: "/Reports/q1.csv".to_string(),
+        };
+        drive.upload(&file, 3, Box::new(std::io::Cursor::new(b"a,b".to_vec()))).await?;
+
+        // Both watchers run a pass within one interval, as two workers would, once the watch is
+        // due again
+        let interval = std::time::Duration::from_millis(400);
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        let sqs_config = aws_sdk_sqs::Config::builder()
+            .behavior_version(aws_sdk_sqs::config::BehaviorVersion::latest())
+            .region(aws_sdk_sqs::config::Region::new("us-east-1"))
+            .endpoint_url(server.uri())
+            .credentials_provider(aws_sdk_sqs::config::Credentials::for_tests())
+            .build();
+        let queue = EventPublisher::new(
+            aws_sdk_sqs::Client::from_conf(sqs_config),
+            Some(format!("{}/000000000000/queue", server.uri())),
+        );
+        for _ in 0..2 {
+            let watcher =
+                FolderWatcher::new(drive.clone(), repository.clone(), queue.clone(), interval);
+            watcher.poll_pass().await?;
+        }
+
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+        Ok(())
+    }
+}
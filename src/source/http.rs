@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::header::LOCATION;
+use reqwest::{redirect, Client, Response, Url};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio_util::io::StreamReader;
+
+use super::{unsupported, Source, SourceFile};
+use crate::messages::FileSource;
+
+// Redirects followed before giving up, like reqwest's default policy
+const MAX_REDIRECTS: usize = 10;
+
+// Largest body of unknown length that is buffered to find its size
+const MAX_BUFFERED_SIZE: usize = 100 * 1024 * 1024;
+
+/// Downloads files from HTTPS URLs, such as pre-signed URLs handed out by producers. Hosts
+/// that resolve to loopback, private or link-local addresses are refused, on every redirect.
+pub struct HttpSource {
+    /// Also download over plain HTTP and from private addresses, for local testing
+    allow_insecure: bool,
+}
+
+impl HttpSource {
+    pub fn new(allow_insecure: bool) -> Self {
+        Self { allow_insecure }
+    }
+
+    /// Follow redirects one at a time, so that every hop is checked before connecting to it
+    async fn get(&self, url: &str) -> Result<Response> {
+        let mut url = Url::parse(url).context("Invalid URL")?;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let response = self.client_for(&url).await?.get(url.clone()).send().await?;
+            if !response.status().is_redirection() {
+                return Ok(response);
+            }
+
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .context("Redirect without a Location header")?;
+            url = url.join(location).context("Invalid redirect location")?;
+        }
+
+        Err(anyhow::anyhow!("Too many redirects"))
+    }
+
+    /// A client that only connects to the public addresses the host of `url` resolves to
+    async fn client_for(&self, url: &Url) -> Result<Client> {
+        let builder = Client::builder().redirect(redirect::Policy::none());
+
+        if self.allow_insecure {
+            return Ok(builder.build()?);
+        }
+
+        if url.scheme() != "https" {
+            return Err(anyhow::anyhow!("Only HTTPS URLs can be synced, got {}", url));
+        }
+
+        let host = url.host_str().context("URL has no host")?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs: Vec<SocketAddr> =
+            match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+                Ok(ip) => vec![SocketAddr::new(ip, port)],
+                Err(_) => tokio::net::lookup_host((host, port))
+                    .await
+                    .with_context(|| format!("Failed to resolve {}", host))?
+                    .collect(),
+            };
+
+        if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+            return Err(anyhow::anyhow!("Refusing to download from non-public address {}", url));
+        }
+
+        // Connect to the checked addresses, not whatever a proxy or another lookup finds
+        Ok(builder.no_proxy().resolve_to_addrs(host, &addrs).build()?)
+    }
+}
+
+impl Default for HttpSource {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+#[async_trait]
+impl Source for HttpSource {
+    async fn open(&self, location: &FileSource) -> Result<SourceFile> {
+        let FileSource::Url { url } = location else {
+            return Err(unsupported("HTTP", location));
+        };
+
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            return Err(anyhow::anyhow!("Only HTTP(S) URLs can be synced, got {}", location));
+        }
+
+        let response =
+            self.get(url).await.with_context(|| format!("Failed to download {}", location))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Downloading {} failed: HTTP {}",
+                location,
+                response.status()
+            ));
+        }
+
+        // Uploads need the size up front, so bodies of unknown length are buffered
+        let Some(size) = response.content_length() else {
+            let mut content = Vec::new();
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                content.extend_from_slice(&chunk.context("Failed to download file")?);
+                if content.len() > MAX_BUFFERED_SIZE {
+                    return Err(anyhow::anyhow!(
+                        "{} has no Content-Length and is larger than {} bytes",
+                        location,
+                        MAX_BUFFERED_SIZE
+                    ));
+                }
+            }
+
+            return Ok(SourceFile {
+                size: content.len() as u64,
+                body: Box::new(std::io::Cursor::new(content)),
+            });
+        };
+
+        let stream = response.bytes_stream().map_err(std::io::Error::other);
+
+        Ok(SourceFile { size, body: Box::new(StreamReader::new(stream)) })
+    }
+}
+
+/// Whether an address is on the internet rather than loopback, private, link-local or
+/// otherwise reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "This network" 0.0.0.0/8, which reaches the local host
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64)
+                // IETF protocol assignments, 192.0.0.0/24
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18)
+                // Reserved 240.0.0.0/4, including the broadcast address
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = embedded_ipv4(ip) {
+                return is_public(ip.into());
+            }
+
+            // Unique local fc00::/7 and link-local fe80::/10
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// The IPv4 address an IPv6 address leads to, for the prefixes that embed one: IPv4-mapped
+/// ::ffff:0:0/96, IPv4-compatible ::/96, NAT64 64:ff9b::/96 and 6to4 2002::/16
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let from = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low] | [0, 0, 0, 0, 0, 0, high, low] => Some(from(high, low)),
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(from(high, low)),
+        [0x2002, high, low, ..] => Some(from(high, low)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_open_presigned_url() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/exports/report.csv"))
+            .and(query_param("signature", "abc"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(&b"a,b,c"[..]))
+            .mount(&server)
+            .await;
+
+        let location =
+            FileSource::Url { url: format!("{}/exports/report.csv?signature=abc", server.uri()) };
+        let mut file = HttpSource::new(true).open(&location).await?;
+
+        let mut content = Vec::new();
+        file.body.read_to_end(&mut content).await?;
+        assert_eq!(file.size, 5);
+        assert_eq!(content, b"a,b,c");
+
+        let missing = FileSource::Url { url: format!("{}/missing.csv", server.uri()) };
+        assert!(HttpSource::new(true).open(&missing).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refuses_plain_http_and_private_addresses() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(&b"secret"[..]))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let source = HttpSource::new(false);
+        for url in [
+            format!("{}/report.csv", server.uri()),
+            "https://127.0.0.1/report.csv".to_string(),
+            "https://localhost/report.csv".to_string(),
+            "https://[::1]/report.csv".to_string(),
+            "https://169.254.169.254/latest/meta-data/".to_string(),
+        ] {
+            assert!(source.open(&FileSource::Url { url }).await.is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in [
+            "93.184.216.34",
+            "2606:2800:220:1:248:1893:25c8:1946",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "0.1.2.3",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.255",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+            "::10.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:c0a8:0101::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::{Component, Path, PathBuf};
+
+use super::{unsupported, Source, SourceFile};
+use crate::messages::FileSource;
+
+/// Reads files from a directory on the worker's filesystem, e.g. a volume shared with producers
+pub struct LocalSource {
+    root: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        resolve_under(&self.root, path)
+    }
+}
+
+/// Resolve a path relative to a root directory, refusing paths that could escape it
+pub(crate) fn resolve_under(root: &Path, path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(anyhow::anyhow!("Local path {} is not a plain relative path", path));
+    }
+
+    Ok(root.join(relative))
+}
+
+#[async_trait]
+impl Source for LocalSource {
+    async fn open(&self, location: &FileSource) -> Result<SourceFile> {
+        let FileSource::Local { path } = location else {
+            return Err(unsupported("Local", location));
+        };
+
+        let full_path = self.resolve(path)?;
+        let file = tokio::fs::File::open(&full_path)
+            .await
+            .with_context(|| format!("Failed to open {}", full_path.display()))?;
+        let size = file.metadata().await?.len();
+
+        Ok(SourceFile { size, body: Box::new(file) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_stays_under_root() {
+        let source = LocalSource::new("/srv/exports");
+
+        assert_eq!(
+            source.resolve("2025/report.pdf").unwrap(),
+            Path::new("/srv/exports/2025/report.pdf")
+        );
+        assert_eq!(source.resolve("/report.pdf").unwrap(), Path::new("/srv/exports/report.pdf"));
+        assert!(source.resolve("../etc/passwd").is_err());
+        assert!(source.resolve("2025/../../etc/passwd").is_err());
+    }
+}
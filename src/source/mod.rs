@@ -0,0 +1,61 @@
+pub mod azure;
+pub mod http;
+pub mod local;
+pub mod s3;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::io::AsyncRead;
+
+use crate::messages::FileSource;
+use azure::AzureBlobSource;
+use http::HttpSource;
+use local::LocalSource;
+use s3::S3Source;
+
+/// A file opened for reading
+pub struct SourceFile {
+    pub size: u64,
+    pub body: Box<dyn AsyncRead + Send + Unpin>,
+}
+
+/// Storage that synced files are read from
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// Open a file for reading
+    async fn open(&self, location: &FileSource) -> Result<SourceFile>;
+}
+
+/// Every source the worker can read from, each opening the locations of its kind
+pub struct Sources {
+    pub s3: S3Source,
+    /// Only available when a root directory is configured
+    pub local: Option<LocalSource>,
+    pub http: HttpSource,
+    /// Only available when a storage account is configured
+    pub azure: Option<AzureBlobSource>,
+}
+
+#[async_trait]
+impl Source for Sources {
+    async fn open(&self, location: &FileSource) -> Result<SourceFile> {
+        match location {
+            FileSource::S3 { .. } => self.s3.open(location).await,
+            FileSource::Local { .. } => {
+                let local = self.local.as_ref().context("No LOCAL_SOURCE_ROOT is configured")?;
+                local.open(location).await
+            }
+            FileSource::Url { .. } => self.http.open(location).await,
+            FileSource::AzureBlob { .. } => {
+                let azure =
+                    self.azure.as_ref().context("No AZURE_STORAGE_ACCOUNT is configured")?;
+                azure.open(location).await
+            }
+        }
+    }
+}
+
+/// Error for a location handed to a source of another kind
+fn unsupported(source: &str, location: &FileSource) -> anyhow::Error {
+    anyhow::anyhow!("{} source can't open {}", source, location)
+}
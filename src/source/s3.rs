@@ -0,0 +1,39 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+
+use super::{unsupported, Source, SourceFile};
+use crate::messages::FileSource;
+
+/// Reads objects from S3 or an S3-compatible store
+pub struct S3Source {
+    client: aws_sdk_s3::Client,
+}
+
+impl S3Source {
+    pub fn new(client: aws_sdk_s3::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Source for S3Source {
+    async fn open(&self, location: &FileSource) -> Result<SourceFile> {
+        let FileSource::S3 { bucket, key } = location else {
+            return Err(unsupported("S3", location));
+        };
+
+        let object = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .context("Failed to download file from S3")?;
+
+        Ok(SourceFile {
+            size: object.content_length().unwrap_or_default() as u64,
+            body: Box::new(object.body.into_async_read()),
+        })
+    }
+}
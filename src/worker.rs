@@ -0,0 +1,1356 @@
+//! Handling of queued messages: authorizations, disconnects, file syncs, exports and watches
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::cmp::min;
+use std::sync::Arc;
+
+use crate::config;
+use crate::db::models::{OAuthGrant, Provider, SyncJobStatus};
+use crate::db::models::{SftpCredentials, WatchedFolder, WebDavCredentials};
+use crate::db::repository::{
+    IntegrationRepository, JobRepository, OAuthRepository, SftpRepository, WatchRepository,
+    WebDavRepository,
+};
+use crate::destination::{Destination, DestinationPath, Destinations};
+use crate::dropbox::{DropboxClient, DropboxEndpoints};
+use crate::export::{export_key, ObjectStore};
+use crate::google_drive::{GoogleDriveClient, GoogleEndpoints};
+use crate::messages::{parse_message, FileSource, MessageType};
+use crate::onedrive;
+use crate::onedrive::credential::ClientCredential;
+use crate::onedrive::drive::{DriveItem, DriveItemRef, DriveOperations, DriveTarget};
+use crate::onedrive::TokenProvider;
+use crate::source::azure::{AzureBlobSource, AzureCredential};
+use crate::source::Source;
+
+/// Authenticate to Microsoft with a certificate when one is configured, otherwise with the
+/// client secret
+pub fn onedrive_credential(config: &config::Config) -> Result<ClientCredential> {
+    match (&config.onedrive_certificate_key_path, &config.onedrive_certificate_thumbprint) {
+        (Some(key_path), Some(thumbprint)) => {
+            let private_key = std::fs::read(key_path)
+                .with_context(|| format!("Failed to read certificate key {}", key_path))?;
+            ClientCredential::certificate(&private_key, thumbprint)
+        }
+        (None, None) => Ok(ClientCredential::Secret(config.onedrive_client_secret.clone())),
+        _ => Err(anyhow::anyhow!(
+            "ONEDRIVE_CERTIFICATE_KEY_PATH and ONEDRIVE_CERTIFICATE_THUMBPRINT must be set together"
+        )),
+    }
+}
+
+/// The Azure Storage account file syncs can read blobs from, if one is configured. The account
+/// key takes precedence over a SAS token.
+pub fn azure_blob_source(config: &config::Config) -> Option<AzureBlobSource> {
+    let account = config.azure_storage_account.clone()?;
+    let credential = match (&config.azure_storage_key, &config.azure_storage_sas_token) {
+        (Some(key), _) => AzureCredential::SharedKey(key.clone()),
+        (None, Some(sas)) => AzureCredential::Sas(sas.clone()),
+        (None, None) => AzureCredential::Anonymous,
+    };
+
+    Some(AzureBlobSource::new(account, config.azure_blob_endpoint.clone(), credential))
+}
+
+/// The client for users' Google Drives, if Google OAuth client credentials are configured
+pub fn google_drive_client(
+    config: &config::Config,
+    repository: Arc<dyn OAuthRepository>,
+) -> Option<GoogleDriveClient> {
+    let client_id = config.google_client_id.clone()?;
+    let client_secret = config.google_client_secret.clone()?;
+    let endpoints = GoogleEndpoints {
+        token_url: config.google_token_url.clone(),
+        api_url: config.google_api_url.clone(),
+    };
+
+    Some(GoogleDriveClient::new(
+        repository,
+        onedrive::RefreshLocks::new(),
+        client_id,
+        client_secret,
+        endpoints,
+    ))
+}
+
+/// The client for users' Dropbox accounts, if a Dropbox app key and secret are configured
+pub fn dropbox_client(
+    config: &config::Config,
+    repository: Arc<dyn OAuthRepository>,
+) -> Option<DropboxClient> {
+    let app_key = config.dropbox_app_key.clone()?;
+    let app_secret = config.dropbox_app_secret.clone()?;
+    let endpoints = DropboxEndpoints {
+        api_url: config.dropbox_api_url.clone(),
+        content_url: config.dropbox_content_url.clone(),
+    };
+
+    Some(DropboxClient::new(
+        repository,
+        onedrive::RefreshLocks::new(),
+        app_key,
+        app_secret,
+        endpoints,
+    ))
+}
+
+/// The Microsoft endpoints integrations use unless they are in another national cloud
+pub fn onedrive_endpoints(config: &config::Config) -> onedrive::Endpoints {
+    onedrive::Endpoints {
+        authority_url: config.microsoft_authority_url.clone(),
+        tenant: config.microsoft_tenant.clone(),
+        graph_url: config.microsoft_graph_url.clone(),
+    }
+}
+
+/// Reject authorization messages that sat in the queue for too long, their credentials are
+/// likely to have been superseded or expired
+fn check_authorization_age(timestamp: DateTime<Utc>, config: &config::Config) -> Result<()> {
+    let max_age = chrono::Duration::seconds(config.authorization_max_age_secs);
+
+    if timestamp < Utc::now() - max_age {
+        return Err(anyhow::anyhow!(
+            "Rejected authorization from {}, older than {} seconds",
+            timestamp,
+            config.authorization_max_age_secs
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check a freshly authorized integration's permissions and record the account behind it.
+/// Integrations without the required permission are rejected; other failures are only logged
+/// since Graph may be briefly unavailable.
+async fn verify_account(drive: &dyn DriveOperations, owner_id: i64, user_id: i64) -> Result<()> {
+    match drive.verify_account(owner_id, user_id).await {
+        Ok(account) => {
+            println!(
+                "OneDrive integration is now ready for use for {} ({} drive {})",
+                account.upn.as_deref().unwrap_or("unknown account"),
+                account.drive_type,
+                account.drive_id
+            );
+            Ok(())
+        }
+        Err(e) => {
+            if let Some(onedrive::OneDriveError::ReauthorizationRequired(reason)) = e.downcast_ref()
+            {
+                return Err(anyhow::anyhow!("Rejected OneDrive authorization: {}", reason));
+            }
+
+            println!(
+                "Warning: Could not verify OneDrive account for owner {}, user {}: {}",
+                owner_id, user_id, e
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Whether a user's integration can be used right now. Integrations that need the user to
+/// authorize again can't, and retrying is pointless until they do.
+async fn integration_ready(
+    tokens: &dyn TokenProvider,
+    owner_id: i64,
+    user_id: i64,
+) -> Result<bool> {
+    match tokens.get_access_token(owner_id, user_id).await {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            if let Some(onedrive::OneDriveError::ReauthorizationRequired(reason)) = e.downcast_ref()
+            {
+                println!(
+                    "Skipping file sync, OneDrive reauthorization required for owner {}, user {}: {}",
+                    owner_id, user_id, reason
+                );
+                return Ok(false);
+            }
+
+            println!("Error getting access token: {}", e);
+            Err(anyhow::anyhow!("Failed to get OneDrive access token: {}", e))
+        }
+    }
+}
+
+/// Why a file sync or export through a user's OneDrive connection, requested at `requested_at`,
+/// can't run right now, if it can't
+async fn onedrive_skip_reason(
+    integrations: &dyn IntegrationRepository,
+    tokens: &dyn TokenProvider,
+    owner_id: i64,
+    user_id: i64,
+    requested_at: DateTime<Utc>,
+) -> Result<Option<&'static str>> {
+    // Work that was queued before the user disconnected OneDrive is cancelled
+    if let Some(disconnected_at) = integrations.get_disconnected_at(owner_id, user_id).await? {
+        if requested_at <= disconnected_at {
+            println!("Skipping work requested before OneDrive was disconnected");
+            return Ok(Some("Requested before OneDrive was disconnected"));
+        }
+    }
+
+    // Make sure the integration is usable before downloading anything
+    if !integration_ready(tokens, owner_id, user_id).await? {
+        return Ok(Some("OneDrive reauthorization required"));
+    }
+
+    Ok(None)
+}
+
+/// User whose OneDrive connection is used: the given one, or the owner's default connection
+async fn onedrive_user_id(
+    integrations: &dyn IntegrationRepository,
+    owner_id: i64,
+    user_id: Option<i64>,
+) -> Result<i64> {
+    match user_id {
+        Some(user_id) => Ok(user_id),
+        None => integrations
+            .get_default_user_id(owner_id)
+            .await?
+            .context("Owner has no default OneDrive connection"),
+    }
+}
+
+/// Why a file sync through a user's connection to `provider` can't run right now, given whether
+/// that connection is active or `None` if there is none
+fn connection_skip_reason(
+    provider: &str,
+    is_active: Option<bool>,
+    owner_id: i64,
+    user_id: i64,
+) -> Option<String> {
+    match is_active {
+        Some(true) => None,
+        Some(false) => {
+            println!(
+                "Skipping file sync, {} reauthorization required for owner {}, user {}",
+                provider, owner_id, user_id
+            );
+            Some(format!("{} reauthorization required", provider))
+        }
+        None => Some(format!("{} is not connected", provider)),
+    }
+}
+
+/// Path a synced file is written to: the source file's name in the destination folder
+fn sync_path(destination: &str, location: &FileSource) -> Result<String> {
+    let file_name = location.file_name().context("Source has no file name")?;
+
+    Ok(format!("{}/{}", destination.trim_end_matches('/'), file_name))
+}
+
+/// Copy a file from its source to its destination
+async fn sync_file(
+    source: &dyn Source,
+    destination: &dyn Destination,
+    location: &FileSource,
+    file: &DestinationPath,
+) -> Result<DriveItem> {
+    let opened = source.open(location).await?;
+
+    let mut item = destination
+        .upload(file, opened.size, opened.body)
+        .await
+        .context("Failed to upload file")?;
+    item.size.get_or_insert(opened.size);
+
+    Ok(item)
+}
+
+/// Everything messages are handled with
+pub struct Services<'a> {
+    pub integrations: &'a dyn IntegrationRepository,
+    pub jobs: &'a dyn JobRepository,
+    pub oauth: &'a dyn OAuthRepository,
+    pub webdav: &'a dyn WebDavRepository,
+    pub sftp: &'a dyn SftpRepository,
+    pub watches: &'a dyn WatchRepository,
+    pub tokens: &'a dyn TokenProvider,
+    pub drive: &'a dyn DriveOperations,
+    pub source: &'a dyn Source,
+    pub destinations: &'a Destinations,
+    pub exports: &'a dyn ObjectStore,
+}
+
+/// Handle one message from the queue
+pub async fn process_message(
+    message_body: &str,
+    config: &config::Config,
+    services: &Services<'_>,
+) -> Result<(), anyhow::Error> {
+    let Services {
+        integrations,
+        jobs,
+        oauth,
+        webdav,
+        sftp,
+        watches,
+        tokens,
+        drive,
+        source,
+        destinations,
+        exports,
+    } = *services;
+    let message = parse_message(message_body).context("Failed to parse message")?;
+
+    match message {
+        MessageType::OneDriveAuthorization { payload } => {
+            println!(
+                "Handling OneDrive authorization for owner: {}, user: {}",
+                payload.owner_id, payload.user_id
+            );
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            let saved = integrations
+                .save_refresh_token(
+                    payload.owner_id,
+                    payload.user_id,
+                    &payload.refresh_token,
+                    payload.scope.as_deref(),
+                    payload.timestamp,
+                )
+                .await
+                .context("Failed to save OneDrive refresh token")?;
+
+            if saved.is_none() {
+                println!(
+                    "Ignoring authorization from {}, a newer one was saved",
+                    payload.timestamp
+                );
+                return Ok(());
+            }
+
+            integrations
+                .save_endpoint_overrides(
+                    payload.owner_id,
+                    payload.user_id,
+                    payload.tenant_id.as_deref(),
+                    payload.cloud,
+                )
+                .await
+                .context("Failed to save OneDrive endpoints")?;
+
+            println!("OneDrive refresh token saved for owner: {}", payload.owner_id);
+
+            // Force a refresh so the new refresh token and its granted scopes are validated, rather
+            // than an access token cached from an earlier authorization
+            match tokens
+                .refresh_access_token_for_user(
+                    payload.owner_id,
+                    payload.user_id,
+                    DateTime::<Utc>::MAX_UTC,
+                )
+                .await
+            {
+                Ok(access_token) => {
+                    println!("Successfully validated refresh token and obtained access token");
+                    println!("Access token: {}...", &access_token[0..min(20, access_token.len())]);
+                    verify_account(drive, payload.owner_id, payload.user_id).await?;
+                }
+                Err(e) => {
+                    println!("Warning: Saved refresh token, but token validation failed: {}", e);
+                    println!("The refresh token may be invalid or expired");
+                }
+            }
+        }
+
+        MessageType::OneDriveAuthorizationCode { payload } => {
+            println!(
+                "Handling OneDrive authorization code for owner: {}, user: {}",
+                payload.owner_id, payload.user_id
+            );
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            tokens
+                .authorize_with_code(&payload)
+                .await
+                .context("Failed to exchange OneDrive authorization code")?;
+
+            verify_account(drive, payload.owner_id, payload.user_id).await?;
+        }
+
+        MessageType::OneDriveTenantAuthorization { payload } => {
+            println!(
+                "Handling OneDrive tenant authorization for owner: {}, tenant: {}",
+                payload.owner_id, payload.tenant_id
+            );
+
+            if payload.drive_user.is_none() && payload.site_id.is_none() {
+                return Err(anyhow::anyhow!("Tenant authorization needs a drive_user or site_id"));
+            }
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            let saved = integrations
+                .save_tenant_integration(
+                    payload.owner_id,
+                    payload.user_id,
+                    &payload.tenant_id,
+                    payload.drive_user.as_deref(),
+                    payload.site_id.as_deref(),
+                    payload.timestamp,
+                )
+                .await
+                .context("Failed to save OneDrive tenant integration")?;
+
+            if saved.is_none() {
+                println!(
+                    "Ignoring authorization from {}, a newer one was saved",
+                    payload.timestamp
+                );
+                return Ok(());
+            }
+
+            integrations
+                .save_endpoint_overrides(
+                    payload.owner_id,
+                    payload.user_id,
+                    Some(&payload.tenant_id),
+                    payload.cloud,
+                )
+                .await
+                .context("Failed to save OneDrive endpoints")?;
+
+            match drive.verify_account(payload.owner_id, payload.user_id).await {
+                Ok(account) => println!(
+                    "OneDrive tenant integration is now ready for use with {} drive {}",
+                    account.drive_type, account.drive_id
+                ),
+                Err(e) => {
+                    println!("Warning: Saved tenant integration, but drive access failed: {}", e);
+                    println!("Admin consent may not have been granted for this tenant");
+                }
+            }
+        }
+
+        MessageType::FileSync { payload } => {
+            let location = payload.source().context("File sync has no source")?;
+
+            println!("Handling file sync request for owner: {}", payload.owner_id);
+            println!("  - Source: {}", location);
+            println!("  - Destination: {} ({:?})", payload.destination, payload.provider);
+
+            let destination = destinations.get(payload.provider)?;
+
+            // OneDrive syncs that don't name a user go to the owner's default connection
+            let user_id = match (payload.provider, payload.user_id) {
+                (Provider::OneDrive, None) => Some(
+                    integrations
+                        .get_default_user_id(payload.owner_id)
+                        .await?
+                        .context("Owner has no default OneDrive connection")?,
+                ),
+                (Provider::GoogleDrive, None) => {
+                    return Err(anyhow::anyhow!("Google Drive syncs need a user_id"));
+                }
+                (Provider::Dropbox, None) => {
+                    return Err(anyhow::anyhow!("Dropbox syncs need a user_id"));
+                }
+                (Provider::WebDav, None) => {
+                    return Err(anyhow::anyhow!("WebDAV syncs need a user_id"));
+                }
+                (Provider::Sftp, None) => {
+                    return Err(anyhow::anyhow!("SFTP syncs need a user_id"));
+                }
+                (_, user_id) => user_id,
+            };
+            if let Some(user_id) = user_id {
+                println!("  - Connection of user: {}", user_id);
+            }
+
+            let file = DestinationPath {
+                owner_id: payload.owner_id,
+                user_id,
+                target: DriveTarget::from_payload(&payload)?,
+                path: sync_path(&payload.destination, &location)?,
+            };
+
+            let job = jobs
+                .create_sync_job(
+                    payload.owner_id,
+                    user_id,
+                    payload.provider,
+                    &location.to_string(),
+                    &file.path,
+                    payload.timestamp,
+                )
+                .await
+                .context("Failed to record file sync")?;
+
+            let skip_reason = match (payload.provider, user_id) {
+                (Provider::OneDrive, Some(user_id)) => onedrive_skip_reason(
+                    integrations,
+                    tokens,
+                    payload.owner_id,
+                    user_id,
+                    payload.timestamp,
+                )
+                .await?
+                .map(String::from),
+                (Provider::GoogleDrive, Some(user_id)) => {
+                    let integration = oauth
+                        .get_oauth_integration(Provider::GoogleDrive, payload.owner_id, user_id)
+                        .await?;
+                    let is_active = integration.map(|integration| integration.is_active);
+                    connection_skip_reason("Google Drive", is_active, payload.owner_id, user_id)
+                }
+                (Provider::Dropbox, Some(user_id)) => {
+                    let integration = oauth
+                        .get_oauth_integration(Provider::Dropbox, payload.owner_id, user_id)
+                        .await?;
+                    let is_active = integration.map(|integration| integration.is_active);
+                    connection_skip_reason("Dropbox", is_active, payload.owner_id, user_id)
+                }
+                (Provider::WebDav, Some(user_id)) => {
+                    // Connections aren't deactivated, a rejected password fails the sync instead
+                    let connection =
+                        webdav.get_webdav_connection(payload.owner_id, user_id).await?;
+                    let is_active = connection.map(|_| true);
+                    connection_skip_reason("WebDAV", is_active, payload.owner_id, user_id)
+                }
+                (Provider::Sftp, Some(user_id)) => {
+                    let connection = sftp.get_sftp_connection(payload.owner_id, user_id).await?;
+                    let is_active = connection.map(|_| true);
+                    connection_skip_reason("SFTP", is_active, payload.owner_id, user_id)
+                }
+                _ => None,
+            };
+            if let Some(reason) = skip_reason {
+                jobs.finish_sync_job(job.id, SyncJobStatus::Skipped, None, Some(&reason)).await?;
+                return Ok(());
+            }
+
+            match sync_file(source, destination, &location, &file).await {
+                Ok(item) => {
+                    println!(
+                        "Uploaded {} ({} bytes) as item {}: {}",
+                        file.path,
+                        item.size.unwrap_or_default(),
+                        item.id,
+                        item.web_url.as_deref().unwrap_or(&item.name)
+                    );
+                    jobs.finish_sync_job(job.id, SyncJobStatus::Succeeded, Some(&item.id), None)
+                        .await?;
+                }
+                Err(e) => {
+                    let error = format!("{:#}", e);
+                    jobs.finish_sync_job(job.id, SyncJobStatus::Failed, None, Some(&error)).await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        MessageType::OneDriveExport { payload } => {
+            let item = DriveItemRef::from_payload(&payload)?;
+
+            println!("Handling OneDrive export request for owner: {}", payload.owner_id);
+            println!("  - Item: {:?}", item);
+            println!("  - Destination: s3://{}/{}", payload.bucket, payload.prefix);
+
+            let user_id = onedrive_user_id(integrations, payload.owner_id, payload.user_id).await?;
+            println!("  - Connection of user: {}", user_id);
+
+            // Exports aren't recorded as jobs, so the reason is only logged
+            if let Some(reason) = onedrive_skip_reason(
+                integrations,
+                tokens,
+                payload.owner_id,
+                user_id,
+                payload.timestamp,
+            )
+            .await?
+            {
+                println!(
+                    "Skipping OneDrive export for owner: {}, user: {}: {}",
+                    payload.owner_id, user_id, reason
+                );
+                return Ok(());
+            }
+
+            let target = match &payload.drive_id {
+                Some(drive_id) => DriveTarget::Drive(drive_id.clone()),
+                None => DriveTarget::Default,
+            };
+            let files = drive
+                .list_files(payload.owner_id, user_id, &target, &item)
+                .await
+                .context("Failed to list files to export")?;
+
+            for file in &files {
+                let key = export_key(&payload.prefix, &file.path);
+                let opened = drive
+                    .download(payload.owner_id, user_id, file)
+                    .await
+                    .with_context(|| format!("Failed to download {}", file.path))?;
+                exports
+                    .put(&payload.bucket, &key, opened.size, opened.body)
+                    .await
+                    .with_context(|| format!("Failed to export {}", file.path))?;
+                println!(
+                    "Exported {} ({} bytes) to s3://{}/{}",
+                    file.path, opened.size, payload.bucket, key
+                );
+            }
+
+            println!("Exported {} files for owner: {}", files.len(), payload.owner_id);
+        }
+
+        MessageType::OneDriveWatch { payload } => {
+            println!("Handling OneDrive watch for owner: {}", payload.owner_id);
+
+            let user_id = onedrive_user_id(integrations, payload.owner_id, payload.user_id).await?;
+            let folder = WatchedFolder {
+                folder_path: payload.folder_path,
+                drive_id: payload.drive_id,
+                bucket: payload.bucket,
+                prefix: payload.prefix,
+            };
+
+            match watches
+                .save_onedrive_watch(payload.owner_id, user_id, &folder, payload.timestamp)
+                .await
+                .context("Failed to save watched folder")?
+            {
+                Some(watch) => println!(
+                    "Watching {} for owner: {}, user: {}, exporting to s3://{}/{}",
+                    watch.folder_path, watch.owner_id, watch.user_id, watch.bucket, watch.prefix
+                ),
+                None => {
+                    println!("Ignoring watch from {}, a newer one was saved", payload.timestamp)
+                }
+            }
+        }
+
+        MessageType::OneDriveDisconnect { payload } => {
+            println!(
+                "Handling OneDrive disconnect for owner: {}, user: {}",
+                payload.owner_id, payload.user_id
+            );
+
+            // A disconnect delivered after the user connected again leaves the new connection alone
+            if integrations
+                .disconnect_integration(payload.owner_id, payload.user_id, payload.timestamp)
+                .await
+                .context("Failed to disconnect OneDrive integration")?
+            {
+                println!(
+                    "OneDrive disconnected for owner: {}, user: {}",
+                    payload.owner_id, payload.user_id
+                );
+            } else {
+                println!(
+                    "No OneDrive integration authorized before {} found for owner: {}, user: {}",
+                    payload.timestamp, payload.owner_id, payload.user_id
+                );
+            }
+        }
+
+        MessageType::GoogleDriveAuthorization { payload } => {
+            println!(
+                "Handling Google Drive authorization for owner: {}, user: {}",
+                payload.owner_id, payload.user_id
+            );
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            let grant = OAuthGrant {
+                refresh_token: payload.refresh_token,
+                account_id: None,
+                granted_scopes: payload.scope,
+                authorized_at: payload.timestamp,
+            };
+            let saved = oauth
+                .save_oauth_refresh_token(
+                    Provider::GoogleDrive,
+                    payload.owner_id,
+                    payload.user_id,
+                    &grant,
+                )
+                .await
+                .context("Failed to save Google Drive refresh token")?;
+
+            match saved {
+                Some(_) => {
+                    println!("Google Drive refresh token saved for owner: {}", payload.owner_id)
+                }
+                None => println!(
+                    "Ignoring authorization from {}, a newer one was saved",
+                    payload.timestamp
+                ),
+            }
+        }
+
+        MessageType::DropboxAuthorization { payload } => {
+            println!(
+                "Handling Dropbox authorization for owner: {}, user: {}",
+                payload.owner_id, payload.user_id
+            );
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            let grant = OAuthGrant {
+                refresh_token: payload.refresh_token,
+                account_id: payload.account_id,
+                granted_scopes: None,
+                authorized_at: payload.timestamp,
+            };
+            let saved = oauth
+                .save_oauth_refresh_token(
+                    Provider::Dropbox,
+                    payload.owner_id,
+                    payload.user_id,
+                    &grant,
+                )
+                .await
+                .context("Failed to save Dropbox refresh token")?;
+
+            match saved {
+                Some(_) => println!("Dropbox refresh token saved for owner: {}", payload.owner_id),
+                None => println!(
+                    "Ignoring authorization from {}, a newer one was saved",
+                    payload.timestamp
+                ),
+            }
+        }
+
+        MessageType::WebDavConnection { payload } => {
+            println!(
+                "Handling WebDAV connection to {} for owner: {}, user: {}",
+                payload.url, payload.owner_id, payload.user_id
+            );
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            let url = reqwest::Url::parse(&payload.url).context("Invalid WebDAV URL")?;
+            // Basic auth sends the password with every request, so plain http is only for local
+            // testing
+            match url.scheme() {
+                "https" => {}
+                "http" if config.allow_insecure_urls => {}
+                _ => return Err(anyhow::anyhow!("WebDAV URLs must be https")),
+            }
+
+            let credentials = WebDavCredentials {
+                url: payload.url,
+                username: payload.username,
+                password: payload.password,
+            };
+            let saved = webdav
+                .save_webdav_connection(
+                    payload.owner_id,
+                    payload.user_id,
+                    &credentials,
+                    payload.timestamp,
+                )
+                .await
+                .context("Failed to save WebDAV connection")?;
+
+            match saved {
+                Some(_) => println!("WebDAV connection saved for owner: {}", payload.owner_id),
+                None => println!(
+                    "Ignoring connection from {}, a newer one was saved",
+                    payload.timestamp
+                ),
+            }
+        }
+
+        MessageType::SftpConnection { payload } => {
+            println!(
+                "Handling SFTP connection to {} for owner: {}, user: {}",
+                payload.host, payload.owner_id, payload.user_id
+            );
+
+            check_authorization_age(payload.timestamp, config)?;
+
+            let credentials = SftpCredentials {
+                host: payload.host,
+                port: payload.port.unwrap_or(22).into(),
+                username: payload.username,
+                private_key: payload.private_key,
+                host_key: payload.host_key,
+                root: payload.root.unwrap_or_default(),
+            };
+            let saved = sftp
+                .save_sftp_connection(
+                    payload.owner_id,
+                    payload.user_id,
+                    &credentials,
+                    payload.timestamp,
+                )
+                .await
+                .context("Failed to save SFTP connection")?;
+
+            match saved {
+                Some(_) => println!("SFTP connection saved for owner: {}", payload.owner_id),
+                None => println!(
+                    "Ignoring connection from {}, a newer one was saved",
+                    payload.timestamp
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryRepository;
+    use crate::destination::local::LocalDestination;
+    use crate::export::memory::MemoryStore;
+    use crate::messages::FileSyncPayload;
+    use crate::onedrive::fake::FakeDrive;
+    use crate::source::local::LocalSource;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn test_config() -> config::Config {
+        config::Config {
+            database_url: "postgres://localhost/unused".to_string(),
+            queue_url: "http://localhost/queue".to_string(),
+            events_queue_url: None,
+            aws_region: "us-east-1".to_string(),
+            s3_bucket: "ferris-file-sync-bucket".to_string(),
+            s3_endpoint: None,
+            local_source_root: None,
+            local_destination_root: None,
+            azure_storage_account: None,
+            azure_storage_key: None,
+            azure_storage_sas_token: None,
+            azure_blob_endpoint: None,
+            encryption_key: "test-encryption-key".to_string(),
+            onedrive_client_id: "test-client-id".to_string(),
+            onedrive_client_secret: "test-client-secret".to_string(),
+            onedrive_certificate_key_path: None,
+            onedrive_certificate_thumbprint: None,
+            microsoft_authority_url: "https://login.microsoftonline.com".to_string(),
+            microsoft_tenant: "common".to_string(),
+            microsoft_graph_url: "https://graph.microsoft.com/v1.0".to_string(),
+            google_client_id: None,
+            google_client_secret: None,
+            google_token_url: "https://oauth2.googleapis.com/token".to_string(),
+            google_api_url: "https://www.googleapis.com".to_string(),
+            dropbox_app_key: None,
+            dropbox_app_secret: None,
+            dropbox_api_url: "https://api.dropboxapi.com".to_string(),
+            dropbox_content_url: "https://content.dropboxapi.com".to_string(),
+            sftp_program: "sftp".to_string(),
+            allow_insecure_urls: false,
+            token_refresh_interval_secs: 60,
+            watch_poll_interval_secs: 300,
+            token_refresh_lead_secs: 600,
+            refresh_token_keepalive_days: 30,
+            authorization_max_age_secs: 3600,
+        }
+    }
+
+    /// Process a message against in-memory storage and a fake drive, reading files from a
+    /// local directory
+    async fn process(
+        message: serde_json::Value,
+        repository: &InMemoryRepository,
+        drive: &Arc<FakeDrive>,
+        source: &LocalSource,
+    ) -> Result<()> {
+        let destinations = Destinations {
+            onedrive: drive.clone(),
+            local: None,
+            google_drive: None,
+            dropbox: None,
+            webdav: drive.clone(),
+            sftp: drive.clone(),
+        };
+        process_with(message, repository, drive, source, &destinations).await
+    }
+
+    async fn process_with(
+        message: serde_json::Value,
+        repository: &InMemoryRepository,
+        drive: &Arc<FakeDrive>,
+        source: &LocalSource,
+        destinations: &Destinations,
+    ) -> Result<()> {
+        let services = Services {
+            integrations: repository,
+            jobs: repository,
+            oauth: repository,
+            webdav: repository,
+            sftp: repository,
+            watches: repository,
+            tokens: drive.as_ref(),
+            drive: drive.as_ref(),
+            source,
+            destinations,
+            exports: &MemoryStore::default(),
+        };
+
+        process_message(&message.to_string(), &test_config(), &services).await
+    }
+
+    fn authorization(
+        user_id: i64,
+        refresh_token: &str,
+        timestamp: DateTime<Utc>,
+    ) -> serde_json::Value {
+        json!({
+            "event_type": "onedrive_authorization",
+            "payload": {
+                "refresh_token": refresh_token,
+                "owner_id": 123,
+                "user_id": user_id,
+                "timestamp": timestamp
+            }
+        })
+    }
+
+    fn s3_source(key: &str) -> FileSource {
+        FileSource::S3 { bucket: "ferris-file-sync-bucket".to_string(), key: key.to_string() }
+    }
+
+    fn file_sync_payload(key: &str, destination: &str) -> FileSyncPayload {
+        FileSyncPayload {
+            source: Some(s3_source(key)),
+            bucket: None,
+            key: None,
+            destination: destination.to_string(),
+            provider: Provider::OneDrive,
+            owner_id: 123,
+            user_id: None,
+            drive_id: None,
+            site_id: None,
+            group_id: None,
+            channel_id: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_sync_path() {
+        let location = s3_source("exports/2025/report.pdf");
+        assert_eq!(
+            sync_path("/Documents/Reports/", &location).unwrap(),
+            "/Documents/Reports/report.pdf"
+        );
+
+        assert!(sync_path("/Documents/", &s3_source("exports/")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_synced_file_lands_in_channel_folder() -> Result<()> {
+        let drive = FakeDrive::new();
+        let mut payload = file_sync_payload("exports/report.csv", "/Reports");
+        payload.group_id = Some("team".to_string());
+        payload.channel_id = Some("general".to_string());
+
+        let target = DriveTarget::from_payload(&payload)?;
+        let path = sync_path(&payload.destination, &payload.source().unwrap())?;
+        let file =
+            DestinationPath { owner_id: 123, user_id: Some(456), target: target.clone(), path };
+        drive.upload(&file, 5, Box::new(&b"a,b,c"[..])).await?;
+
+        assert_eq!(drive.file(123, 456, &target, "/Reports/report.csv"), Some(b"a,b,c".to_vec()));
+        assert_eq!(drive.file(123, 456, &DriveTarget::Default, "/Reports/report.csv"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_integration_ready_skips_reauthorization() -> Result<()> {
+        let drive = FakeDrive::new();
+        assert!(integration_ready(&drive, 123, 456).await?);
+
+        drive.require_reauthorization(123, 456);
+        assert!(!integration_ready(&drive, 123, 456).await?);
+        assert!(integration_ready(&drive, 123, 789).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_account_rejects_reauthorization() {
+        let drive = FakeDrive::new();
+        assert!(verify_account(&drive, 123, 456).await.is_ok());
+
+        drive.require_reauthorization(123, 456);
+        assert!(verify_account(&drive, 123, 456).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_authorizations_and_disconnect() -> Result<()> {
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new("unused");
+        let now = Utc::now();
+
+        process(authorization(456, "first", now), &repository, &drive, &source).await?;
+        process(authorization(789, "second", now), &repository, &drive, &source).await?;
+        assert_eq!(repository.get_default_user_id(123).await?, Some(456));
+
+        // An authorization that was overtaken in the queue doesn't replace the newer one
+        let stale = authorization(456, "stale", now - chrono::Duration::minutes(1));
+        process(stale, &repository, &drive, &source).await?;
+        assert_eq!(repository.get_refresh_token(123, 456).await?.unwrap().refresh_token, "first");
+
+        // A disconnect that was overtaken by the authorization leaves it connected
+        let late_disconnect = json!({
+            "event_type": "onedrive_disconnect",
+            "payload": { "owner_id": 123, "user_id": 456, "timestamp": now - chrono::Duration::minutes(1) }
+        });
+        process(late_disconnect, &repository, &drive, &source).await?;
+        assert!(repository.get_integration(123, 456).await?.is_some());
+
+        let disconnect = json!({
+            "event_type": "onedrive_disconnect",
+            "payload": { "owner_id": 123, "user_id": 456, "timestamp": Utc::now() }
+        });
+        process(disconnect, &repository, &drive, &source).await?;
+        assert!(repository.get_integration(123, 456).await?.is_none());
+        assert_eq!(repository.get_default_user_id(123).await?, Some(789));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_syncs_that_cannot_run_are_skipped() -> Result<()> {
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new("unused");
+        let queued_at = Utc::now();
+
+        process(authorization(456, "refresh-token", queued_at), &repository, &drive, &source)
+            .await?;
+        repository.disconnect_integration(123, 456, Utc::now()).await?;
+        drive.require_reauthorization(123, 789);
+
+        for user_id in [456, 789] {
+            let file_sync = json!({
+                "event_type": "file_sync",
+                "payload": {
+                    "bucket": "ferris-file-sync-bucket",
+                    "key": "exports/report.pdf",
+                    "destination": "/Reports",
+                    "owner_id": 123,
+                    "user_id": user_id,
+                    "timestamp": queued_at
+                }
+            });
+            process(file_sync, &repository, &drive, &source).await?;
+        }
+
+        let jobs = repository.sync_jobs();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().all(|job| job.status == SyncJobStatus::Skipped));
+        assert_eq!(jobs[0].source, "s3://ferris-file-sync-bucket/exports/report.pdf");
+        assert_eq!(jobs[0].destination, "/Reports/report.pdf");
+        assert_eq!(jobs[0].error.as_deref(), Some("Requested before OneDrive was disconnected"));
+        assert_eq!(jobs[1].error.as_deref(), Some("OneDrive reauthorization required"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_sync_from_local_source() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir(root.path().join("exports"))?;
+        std::fs::write(root.path().join("exports/report.csv"), "a,b,c")?;
+
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new(root.path());
+
+        process(authorization(456, "refresh-token", Utc::now()), &repository, &drive, &source)
+            .await?;
+
+        let file_sync = json!({
+            "event_type": "file_sync",
+            "payload": {
+                "source": { "type": "local", "path": "exports/report.csv" },
+                "destination": "/Reports",
+                "owner_id": 123,
+                "timestamp": Utc::now()
+            }
+        });
+        process(file_sync, &repository, &drive, &source).await?;
+
+        let content = drive.file(123, 456, &DriveTarget::Default, "/Reports/report.csv");
+        assert_eq!(content, Some(b"a,b,c".to_vec()));
+
+        let jobs = repository.sync_jobs();
+        assert_eq!(jobs[0].status, SyncJobStatus::Succeeded);
+        assert_eq!(jobs[0].source, "file://exports/report.csv");
+        assert!(jobs[0].drive_item_id.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_onedrive_export_copies_folder_to_s3() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::write(root.path().join("q1.csv"), "a,b")?;
+        std::fs::write(root.path().join("q2.csv"), "c,d")?;
+
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new(root.path());
+
+        process(authorization(456, "refresh-token", Utc::now()), &repository, &drive, &source)
+            .await?;
+        for (name, destination) in [("q1.csv", "/Reports"), ("q2.csv", "/Reports/2025")] {
+            let file_sync = json!({
+                "event_type": "file_sync",
+                "payload": {
+                    "source": { "type": "local", "path": name },
+                    "destination": destination,
+                    "owner_id": 123,
+                    "timestamp": Utc::now()
+                }
+            });
+            process(file_sync, &repository, &drive, &source).await?;
+        }
+
+        let exports = MemoryStore::default();
+        let destinations = Destinations {
+            onedrive: drive.clone(),
+            local: None,
+            google_drive: None,
+            dropbox: None,
+            webdav: drive.clone(),
+            sftp: drive.clone(),
+        };
+        let services = Services {
+            integrations: &repository,
+            jobs: &repository,
+            oauth: &repository,
+            webdav: &repository,
+            sftp: &repository,
+            watches: &repository,
+            tokens: drive.as_ref(),
+            drive: drive.as_ref(),
+            source: &source,
+            destinations: &destinations,
+            exports: &exports,
+        };
+
+        let export = |item: serde_json::Value, prefix: &str| {
+            let mut payload = json!({
+                "owner_id": 123,
+                "bucket": "exports-bucket",
+                "prefix": prefix,
+                "timestamp": Utc::now()
+            });
+            payload.as_object_mut().unwrap().extend(item.as_object().unwrap().clone());
+            json!({ "event_type": "onedrive_export", "payload": payload }).to_string()
+        };
+        let config = test_config();
+        process_message(&export(json!({ "item_path": "/Reports" }), "backup/"), &config, &services)
+            .await?;
+        process_message(&export(json!({ "item_id": "Reports/q1.csv" }), ""), &config, &services)
+            .await?;
+
+        let objects = exports.objects.lock().unwrap();
+        let object = |key: &str| objects.get(&("exports-bucket".to_string(), key.to_string()));
+        assert_eq!(objects.len(), 3);
+        assert_eq!(object("backup/q1.csv"), Some(&b"a,b".to_vec()));
+        assert_eq!(object("backup/2025/q2.csv"), Some(&b"c,d".to_vec()));
+        assert_eq!(object("q1.csv"), Some(&b"a,b".to_vec()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_sync_to_local_destination_needs_no_connection() -> Result<()> {
+        let source_root = tempfile::tempdir()?;
+        let destination_root = tempfile::tempdir()?;
+        std::fs::write(source_root.path().join("report.csv"), "a,b,c")?;
+
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new(source_root.path());
+        let destinations = Destinations {
+            onedrive: drive.clone(),
+            local: Some(LocalDestination::new(destination_root.path())),
+            google_drive: None,
+            dropbox: None,
+            webdav: drive.clone(),
+            sftp: drive.clone(),
+        };
+
+        let file_sync = json!({
+            "event_type": "file_sync",
+            "payload": {
+                "source": { "type": "local", "path": "report.csv" },
+                "destination": "/Reports",
+                "provider": "local",
+                "owner_id": 123,
+                "timestamp": Utc::now()
+            }
+        });
+        process_with(file_sync, &repository, &drive, &source, &destinations).await?;
+
+        let synced = std::fs::read(destination_root.path().join("123/Reports/report.csv"))?;
+        assert_eq!(synced, b"a,b,c");
+
+        let jobs = repository.sync_jobs();
+        assert_eq!(jobs[0].status, SyncJobStatus::Succeeded);
+        assert_eq!(jobs[0].provider, Provider::Local);
+        assert_eq!(jobs[0].user_id, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_google_drive_syncs_wait_for_authorization() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::write(root.path().join("report.csv"), "a,b,c")?;
+
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new(root.path());
+        // The fake drive stands in for Google Drive too
+        let google_drive = Destinations {
+            onedrive: drive.clone(),
+            local: None,
+            google_drive: Some(drive.clone()),
+            dropbox: None,
+            webdav: drive.clone(),
+            sftp: drive.clone(),
+        };
+        let file_sync = || {
+            json!({
+                "event_type": "file_sync",
+                "payload": {
+                    "source": { "type": "local", "path": "report.csv" },
+                    "destination": "/Reports",
+                    "provider": "google_drive",
+                    "owner_id": 123,
+                    "user_id": 456,
+                    "timestamp": Utc::now()
+                }
+            })
+        };
+
+        process_with(file_sync(), &repository, &drive, &source, &google_drive).await?;
+
+        let authorization = json!({
+            "event_type": "google_drive_authorization",
+            "payload": {
+                "refresh_token": "1//0g-refresh",
+                "owner_id": 123,
+                "user_id": 456,
+                "scope": "https://www.googleapis.com/auth/drive.file",
+                "timestamp": Utc::now()
+            }
+        });
+        process(authorization, &repository, &drive, &source).await?;
+        let refresh_token =
+            repository.get_oauth_refresh_token(Provider::GoogleDrive, 123, 456).await?;
+        assert_eq!(refresh_token.as_deref(), Some("1//0g-refresh"));
+
+        process_with(file_sync(), &repository, &drive, &source, &google_drive).await?;
+
+        let jobs = repository.sync_jobs();
+        assert_eq!(jobs[0].status, SyncJobStatus::Skipped);
+        assert_eq!(jobs[0].error.as_deref(), Some("Google Drive is not connected"));
+        assert_eq!(jobs[1].status, SyncJobStatus::Succeeded);
+        assert_eq!(jobs[1].provider, Provider::GoogleDrive);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dropbox_authorization_keeps_newest() -> Result<()> {
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new(".");
+        let authorization = |refresh_token: &str, timestamp: DateTime<Utc>| {
+            json!({
+                "event_type": "dropbox_authorization",
+                "payload": {
+                    "refresh_token": refresh_token,
+                    "owner_id": 123,
+                    "user_id": 456,
+                    "account_id": "dbid:AAH4f99",
+                    "timestamp": timestamp
+                }
+            })
+        };
+
+        let now = Utc::now();
+        process(authorization("newer", now), &repository, &drive, &source).await?;
+        let older = now - chrono::Duration::minutes(1);
+        process(authorization("older", older), &repository, &drive, &source).await?;
+
+        let refresh_token = repository.get_oauth_refresh_token(Provider::Dropbox, 123, 456).await?;
+        assert_eq!(refresh_token.as_deref(), Some("newer"));
+        let integration =
+            repository.get_oauth_integration(Provider::Dropbox, 123, 456).await?.unwrap();
+        assert_eq!(integration.account_id.as_deref(), Some("dbid:AAH4f99"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_webdav_syncs_wait_for_connection() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::write(root.path().join("report.csv"), "a,b,c")?;
+
+        let repository = InMemoryRepository::new();
+        let drive = Arc::new(FakeDrive::new());
+        let source = LocalSource::new(root.path());
+        let file_sync = || {
+            json!({
+                "event_type": "file_sync",
+                "payload": {
+                    "source": { "type": "local", "path": "report.csv" },
+                    "destination": "/Reports",
+                    "provider": "webdav",
+                    "owner_id": 123,
+                    "user_id": 456,
+                    "timestamp": Utc::now()
+                }
+            })
+        };
+
+        // The fake drive stands in for the WebDAV server
+        process(file_sync(), &repository, &drive, &source).await?;
+
+        let connection = |url: &str| {
+            json!({
+                "event_type": "webdav_connection",
+                "payload": {
+                    "url": url,
+                    "username": "alice",
+                    "password": "app-password",
+                    "owner_id": 123,
+                    "user_id": 456,
+                    "timestamp": Utc::now()
+                }
+            })
+        };
+        // The password would be sent in the clear
+        let insecure = connection("http://cloud.example.com/remote.php/dav/files/alice");
+        assert!(process(insecure, &repository, &drive, &source).await.is_err());
+        assert!(repository.get_webdav_credentials(123, 456).await?.is_none());
+
+        let secure = connection("https://cloud.example.com/remote.php/dav/files/alice");
+        process(secure, &repository, &drive, &source).await?;
+        let credentials = repository.get_webdav_credentials(123, 456).await?.unwrap();
+        assert_eq!(credentials.password, "app-password");
+
+        process(file_sync(), &repository, &drive, &source).await?;
+
+        let jobs = repository.sync_jobs();
+        assert_eq!(jobs[0].status, SyncJobStatus::Skipped);
+        assert_eq!(jobs[0].error.as_deref(), Some("WebDAV is not connected"));
+        assert_eq!(jobs[1].status, SyncJobStatus::Succeeded);
+        assert_eq!(jobs[1].provider, Provider::WebDav);
+
+        Ok(())
+    }
+}